@@ -2,6 +2,25 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::fmt::Debug;
+
+/// An account password, either in plain text or already hashed (crypt(3))
+#[derive(Clone, PartialEq, PartialOrd, Eq, Ord)]
+pub enum Password {
+    Plain(String),
+    Hashed(String),
+}
+
+/// Passwords are never printed
+impl Debug for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Password::Plain(_) => f.write_str("Plain(..)"),
+            Password::Hashed(_) => f.write_str("Hashed(..)"),
+        }
+    }
+}
+
 /// Identifies an account
 #[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord)]
 pub struct Account {
@@ -24,7 +43,7 @@ pub struct Account {
     pub shell: String,
 
     /// New password
-    pub password: Option<String>,
+    pub password: Option<Password>,
 
     /// Builtin user? (root)
     pub builtin: bool,
@@ -90,7 +109,15 @@ impl Account {
     /// Update the password
     pub fn with_password<P: AsRef<str>>(self, p: P) -> Self {
         Self {
-            password: Some(p.as_ref().to_string()),
+            password: Some(Password::Plain(p.as_ref().to_string())),
+            ..self
+        }
+    }
+
+    /// Update the password with a pre-hashed (crypt(3)) password
+    pub fn with_hashed_password<P: AsRef<str>>(self, p: P) -> Self {
+        Self {
+            password: Some(Password::Hashed(p.as_ref().to_string())),
             ..self
         }
     }
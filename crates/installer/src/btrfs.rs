@@ -0,0 +1,52 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Btrfs subvolume layout for a btrfs root
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Filesystem;
+
+/// A subvolume created at the top level of the root filesystem,
+/// and where it is mounted within the target.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Subvolume {
+    /// Subvolume name, i.e. `@home`
+    pub name: String,
+
+    /// Where it is mounted, i.e. `/home`
+    pub mountpoint: PathBuf,
+}
+
+impl Subvolume {
+    /// Create a new subvolume definition
+    pub fn new(name: impl Into<String>, mountpoint: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            mountpoint: mountpoint.into(),
+        }
+    }
+
+    /// The recommended layout: `@` for `/`, split out `/home` and `/var/log`
+    /// so they are unaffected by snapshots and rollbacks of `/`.
+    pub fn default_layout() -> Vec<Self> {
+        vec![
+            Self::new("@", "/"),
+            Self::new("@home", "/home"),
+            Self::new("@var_log", "/var/log"),
+        ]
+    }
+
+    /// Whether the name can be created at the top level: not empty, `.`, `..`, nor nested
+    pub fn has_valid_name(&self) -> bool {
+        !matches!(self.name.as_str(), "" | "." | "..") && !self.name.contains('/')
+    }
+
+    /// Mount options to select this subvolume
+    pub fn mount_options(&self) -> String {
+        format!("{},subvol={}", Filesystem::BTRFS.mount_options(), self.name)
+    }
+}
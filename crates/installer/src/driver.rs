@@ -0,0 +1,580 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Execution driver
+//!
+//! Runs the compiled steps in order and guarantees the cleanups run
+//! afterwards, whether the steps succeed, fail or are interrupted (Ctrl-C),
+//! so a failed install never leaves the target mounted.
+//!
+//! With a [`Journal`], completed steps are recorded as they finish and
+//! skipped when the same model is installed again.
+//!
+//! Progress is reported as [`Event`]s through [`Context::emit`].
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use system::disk::{self, Disk, Layout, Table};
+use thiserror::Error;
+
+use crate::{
+    journal::Journal,
+    steps::{self, Cleanup, Context, Event, InstallLog, Stage, Step},
+};
+
+/// Set by the signal handler, checked between steps
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Catches SIGINT + SIGTERM for as long as it is alive
+struct InterruptGuard {
+    previous: [(libc::c_int, libc::sighandler_t); 2],
+}
+
+impl InterruptGuard {
+    fn install() -> Self {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        let handler = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // SAFETY: The handler only touches an atomic
+        let previous = [libc::SIGINT, libc::SIGTERM].map(|signal| (signal, unsafe { libc::signal(signal, handler) }));
+        Self { previous }
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        for (signal, handler) in self.previous {
+            // SAFETY: Restoring the handler we replaced
+            unsafe { libc::signal(signal, handler) };
+        }
+    }
+}
+
+/// A step which completed successfully
+#[derive(Debug)]
+pub struct Completed {
+    pub name: &'static str,
+    pub title: String,
+    pub description: String,
+
+    /// Completed by a previous run
+    pub resumed: bool,
+}
+
+impl Completed {
+    fn new(stage: &Stage, resumed: bool) -> Self {
+        Self {
+            name: stage.name,
+            title: stage.title.clone(),
+            description: stage.description.clone(),
+            resumed,
+        }
+    }
+}
+
+/// Why a step (or cleanup) did not complete
+#[derive(Debug, Error)]
+pub enum Reason {
+    #[error(transparent)]
+    Step(#[from] steps::Error),
+
+    #[error("interrupted")]
+    Interrupted,
+}
+
+/// A step (or cleanup) which did not complete
+#[derive(Debug)]
+pub struct Failure {
+    pub name: &'static str,
+    pub title: String,
+    pub description: String,
+    pub reason: Reason,
+
+    /// stderr of the last command the step ran, if any
+    pub stderr: Option<String>,
+}
+
+impl Failure {
+    fn new(stage: &Stage, reason: Reason, stderr: Option<String>) -> Self {
+        Self {
+            name: stage.name,
+            title: stage.title.clone(),
+            description: stage.description.clone(),
+            reason,
+            stderr,
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}) {}: {}",
+            self.title, self.name, self.description, self.reason
+        )?;
+        if let Some(stderr) = self.stderr.as_ref().filter(|s| !s.trim().is_empty()) {
+            for line in stderr.trim_end().lines() {
+                write!(f, "\n    {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of a run
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Steps which completed, in order
+    pub completed: Vec<Completed>,
+
+    /// The step that stopped the installation
+    pub failure: Option<Failure>,
+
+    /// Cleanups which failed, these may need manual attention
+    pub cleanup_failures: Vec<Failure>,
+}
+
+impl Report {
+    /// Did every step and cleanup complete?
+    pub fn is_success(&self) -> bool {
+        self.failure.is_none() && self.cleanup_failures.is_empty()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Completed steps:")?;
+        for step in self.completed.iter() {
+            let resumed = if step.resumed { " [previous run]" } else { "" };
+            writeln!(f, "  {} ({}) {}{resumed}", step.title, step.name, step.description)?;
+        }
+        if let Some(failure) = self.failure.as_ref() {
+            writeln!(f, "Failed step:\n  {failure}")?;
+        }
+        if !self.cleanup_failures.is_empty() {
+            writeln!(f, "Failed cleanups:")?;
+            for failure in self.cleanup_failures.iter() {
+                writeln!(f, "  {failure}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wraps the frontend context to keep the stderr of the last captured command
+#[derive(Debug)]
+struct Recorder<'a, C> {
+    inner: &'a C,
+    stderr: Mutex<Option<String>>,
+}
+
+impl<'a, C> Recorder<'a, C> {
+    fn take_stderr(&self) -> Option<String> {
+        self.stderr.lock().ok().and_then(|mut s| s.take())
+    }
+
+    /// stderr for a failed step, preferring that of the failed command
+    fn stderr_for(&self, error: &steps::Error) -> Option<String> {
+        match error {
+            steps::Error::CommandFailed { stderr, .. } => Some(stderr.clone()),
+            _ => self.take_stderr(),
+        }
+    }
+}
+
+/// Mark the start of a step (or cleanup) in the install log
+fn log_stage<'a>(context: &'a impl Context<'a>, stage: &Stage) {
+    if let Some(install_log) = context.install_log() {
+        install_log.line(format!("==> {}: {}", stage.title, stage.description));
+    }
+}
+
+impl<'a, 'r, C: Context<'a> + Sync> Context<'r> for Recorder<'a, C>
+where
+    'a: 'r,
+{
+    fn root(&'r self) -> &'r PathBuf {
+        self.inner.root()
+    }
+
+    fn install_log(&self) -> Option<&InstallLog> {
+        self.inner.install_log()
+    }
+
+    fn emit(&self, event: Event) {
+        self.inner.emit(event)
+    }
+
+    fn run_command(&self, cmd: &mut Command) -> Result<(), steps::Error> {
+        self.inner.run_command(cmd)
+    }
+
+    fn run_command_captured(&self, cmd: &mut Command, input: Option<&str>) -> Result<Output, steps::Error> {
+        let output = self.inner.run_command_captured(cmd, input)?;
+        if let Ok(mut stderr) = self.stderr.lock() {
+            *stderr = Some(String::from_utf8_lossy(&output.stderr).into());
+        }
+        Ok(output)
+    }
+
+    fn run_command_streamed(&self, cmd: &mut Command, on_line: &mut dyn FnMut(&str)) -> Result<(), steps::Error> {
+        self.inner.run_command_streamed(cmd, on_line)
+    }
+
+    fn write_partition_table(
+        &self,
+        disk: &Disk,
+        build: &dyn Fn(&mut Table) -> Result<(), disk::Error>,
+    ) -> Result<Layout, steps::Error> {
+        self.inner.write_partition_table(disk, build)
+    }
+
+    fn read_to_string(&self, path: &Path) -> Result<String, steps::Error> {
+        self.inner.read_to_string(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, steps::Error> {
+        self.inner.read_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), steps::Error> {
+        self.inner.create_dir_all(path)
+    }
+
+    fn write_file(&self, path: &Path, contents: &str) -> Result<(), steps::Error> {
+        self.inner.write_file(path, contents)
+    }
+
+    fn write_private_file(&self, path: &Path, contents: &str) -> Result<(), steps::Error> {
+        self.inner.write_private_file(path, contents)
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path) -> Result<(), steps::Error> {
+        self.inner.copy_file(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), steps::Error> {
+        self.inner.remove_file(path)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> Result<(), steps::Error> {
+        self.inner.symlink(target, link)
+    }
+}
+
+/// Executes steps followed by their cleanups
+pub struct Driver<'a> {
+    steps: Vec<Step<'a>>,
+    cleanups: Vec<Cleanup>,
+    journal: Option<Journal>,
+}
+
+impl<'a> Driver<'a> {
+    /// Create a driver from the output of [`crate::Installer::compile_to_steps`]
+    pub fn new(cleanups: Vec<Cleanup>, steps: Vec<Step<'a>>) -> Self {
+        Self {
+            steps,
+            cleanups,
+            journal: None,
+        }
+    }
+
+    /// Record progress in the journal, resuming from it where possible
+    pub fn with_journal(self, journal: Journal) -> Self {
+        Self {
+            journal: Some(journal),
+            ..self
+        }
+    }
+
+    /// Total number of steps and cleanups
+    pub fn total(&self) -> usize {
+        self.steps.len() + self.cleanups.len()
+    }
+
+    /// Run all steps until one fails or we're interrupted, then run every cleanup.
+    pub fn run<C>(mut self, context: &'a C) -> Report
+    where
+        C: Context<'a> + Sync,
+    {
+        let guard = InterruptGuard::install();
+        let recorder = Recorder {
+            inner: context,
+            stderr: Mutex::new(None),
+        };
+        let total = self.total();
+        let mut report = Report::default();
+        let stage = |index, cleanup, name, title: String, description: String| Stage {
+            index,
+            total,
+            cleanup,
+            name,
+            title,
+            description,
+        };
+
+        // Steps which ran, so only their cleanups are needed
+        let mut executed = vec![];
+
+        // Only an unbroken run of completed steps can be skipped
+        let mut resuming = self.journal.as_ref().is_some_and(|j| !j.is_empty());
+        for (index, step) in self.steps.iter().enumerate() {
+            let stage = stage(index, false, step.name(), step.title(), step.describe());
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                report.failure = Some(Failure::new(&stage, Reason::Interrupted, None));
+                break;
+            }
+
+            resuming = resuming && self.journal.as_ref().is_some_and(|j| j.is_completed(index, step));
+            if resuming && step.is_resumable() {
+                log::info!("Skipping {}, completed by a previous run", step.name());
+                report.completed.push(Completed::new(&stage, true));
+                context.emit(Event::Skipped(stage));
+                continue;
+            }
+
+            context.emit(Event::Started(stage.clone()));
+            log_stage(context, &stage);
+            recorder.take_stderr();
+            if let Err(e) = step.execute(&recorder) {
+                log::error!("Step {} failed: {e}", step.name());
+                let stderr = recorder.stderr_for(&e);
+                let error = e.to_string();
+                report.failure = Some(Failure::new(&stage, Reason::Step(e), stderr));
+                context.emit(Event::Failed { stage, error });
+                break;
+            }
+            executed.push(step);
+            report.completed.push(Completed::new(&stage, false));
+            context.emit(Event::Finished(stage));
+            if let Some(Err(e)) = self.journal.as_mut().map(|j| j.record(index, step)) {
+                log::warn!("Failed to update the journal: {e}");
+            }
+        }
+
+        // Nothing left to resume
+        if report.failure.is_none() {
+            if let Some(Err(e)) = self.journal.take().map(Journal::remove) {
+                log::warn!("Failed to remove the journal: {e}");
+            }
+        }
+
+        // Cleanups are best effort: keep going so as much as possible is undone
+        let offset = self.steps.len();
+        for (index, cleanup) in self.cleanups.iter().enumerate() {
+            let stage = stage(
+                offset + index,
+                true,
+                cleanup.name(),
+                cleanup.title(),
+                cleanup.describe(),
+            );
+            if !executed.iter().any(|step| cleanup.undoes(step)) {
+                log::trace!("Skipping {}, nothing to undo", cleanup.name());
+                context.emit(Event::Skipped(stage));
+                continue;
+            }
+            context.emit(Event::Started(stage.clone()));
+            log_stage(context, &stage);
+            recorder.take_stderr();
+            if let Err(e) = cleanup.execute(&recorder) {
+                log::error!("Cleanup {} failed: {e}", cleanup.name());
+                let stderr = recorder.stderr_for(&e);
+                let error = e.to_string();
+                report
+                    .cleanup_failures
+                    .push(Failure::new(&stage, Reason::Step(e), stderr));
+                context.emit(Event::Failed { stage, error });
+            } else {
+                context.emit(Event::Finished(stage));
+            }
+        }
+
+        drop(guard);
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::unix::process::ExitStatusExt,
+        path::{Path, PathBuf},
+        process::{Command, ExitStatus, Output},
+        sync::Mutex,
+    };
+
+    use super::{Completed, Driver, Failure, Reason, Report};
+    use crate::steps::{self, Cleanup, Context, MountPartition, Step, Unmount};
+
+    /// The driver's interrupt handling is process wide
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    /// Records commands instead of running them, failing or raising SIGINT on request
+    #[derive(Debug, Default)]
+    struct Mock {
+        root: PathBuf,
+        commands: Mutex<Vec<String>>,
+        fail: Option<&'static str>,
+        interrupt: Option<&'static str>,
+    }
+
+    impl Mock {
+        fn commands(&self) -> Vec<String> {
+            self.commands.lock().unwrap().clone()
+        }
+    }
+
+    impl<'a> Context<'a> for Mock {
+        fn root(&'a self) -> &'a PathBuf {
+            &self.root
+        }
+
+        fn run_command_captured(&self, cmd: &mut Command, _input: Option<&str>) -> Result<Output, steps::Error> {
+            let line = std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|a| a.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" ");
+            self.commands.lock().unwrap().push(line.clone());
+            if self.interrupt == Some(line.as_str()) {
+                // SAFETY: The driver's handler only sets a flag
+                unsafe { libc::raise(libc::SIGINT) };
+            }
+            let status = ExitStatus::from_raw(if self.fail == Some(line.as_str()) { 1 << 8 } else { 0 });
+            if !status.success() {
+                return Err(steps::Error::CommandFailed {
+                    program: cmd.get_program().to_string_lossy().into(),
+                    status,
+                    stdout: String::new(),
+                    stderr: "mount: failed\n".into(),
+                });
+            }
+            Ok(Output {
+                status,
+                stdout: vec![],
+                stderr: vec![],
+            })
+        }
+
+        fn create_dir_all(&self, _path: &Path) -> Result<(), steps::Error> {
+            Ok(())
+        }
+    }
+
+    /// Mount each directory from a device of the same name, unmounting in reverse as compiled
+    fn driver<'a>(dirs: &[&str]) -> Driver<'a> {
+        let steps = dirs
+            .iter()
+            .map(|dir| {
+                Step::mount(MountPartition {
+                    device: Path::new("/dev").join(dir),
+                    mountpoint: Path::new("/").join(dir),
+                    options: None,
+                })
+            })
+            .collect();
+        let cleanups = dirs
+            .iter()
+            .rev()
+            .map(|dir| {
+                Cleanup::unmount(Unmount {
+                    mountpoint: Path::new("/").join(dir),
+                })
+            })
+            .collect();
+        Driver::new(cleanups, steps)
+    }
+
+    #[test]
+    fn test_run() {
+        let _serial = SERIAL.lock().unwrap();
+        let context = Mock::default();
+        let report = driver(&["a", "b"]).run(&context);
+        assert!(report.is_success());
+        assert_eq!(report.completed.len(), 2);
+        assert_eq!(
+            context.commands(),
+            ["mount /dev/a /a", "mount /dev/b /b", "umount /b", "umount /a"]
+        );
+    }
+
+    #[test]
+    fn test_run_failure() {
+        let _serial = SERIAL.lock().unwrap();
+        let context = Mock {
+            fail: Some("mount /dev/b /b"),
+            ..Default::default()
+        };
+        let report = driver(&["a", "b", "c"]).run(&context);
+        assert!(!report.is_success());
+        assert_eq!(report.completed.len(), 1);
+        let failure = report.failure.unwrap();
+        assert_eq!(failure.description, "/dev/b as /b");
+        assert!(matches!(
+            failure.reason,
+            Reason::Step(steps::Error::CommandFailed { .. })
+        ));
+        assert_eq!(failure.stderr.as_deref(), Some("mount: failed\n"));
+
+        // Neither the failed step nor those after it are undone
+        assert_eq!(context.commands(), ["mount /dev/a /a", "mount /dev/b /b", "umount /a"]);
+    }
+
+    #[test]
+    fn test_run_interrupted() {
+        let _serial = SERIAL.lock().unwrap();
+        let context = Mock {
+            interrupt: Some("mount /dev/a /a"),
+            ..Default::default()
+        };
+        let report = driver(&["a", "b"]).run(&context);
+        assert_eq!(report.completed.len(), 1);
+        let failure = report.failure.unwrap();
+        assert_eq!(failure.description, "/dev/b as /b");
+        assert!(matches!(failure.reason, Reason::Interrupted));
+        assert_eq!(context.commands(), ["mount /dev/a /a", "umount /a"]);
+
+        // The next run starts afresh
+        let context = Mock::default();
+        assert!(driver(&["a"]).run(&context).is_success());
+    }
+
+    #[test]
+    fn test_report() {
+        let report = Report {
+            completed: vec![Completed {
+                name: "mount-partition",
+                title: "Mount partition".into(),
+                description: "/dev/sda3".into(),
+                resumed: true,
+            }],
+            failure: Some(Failure {
+                name: "install-packages",
+                title: "Install".into(),
+                description: "42 packages".into(),
+                reason: Reason::Step(steps::Error::NoKernel),
+                stderr: Some("moss: no such package\n".into()),
+            }),
+            cleanup_failures: vec![],
+        };
+        assert!(!report.is_success());
+        let text = report.to_string();
+        assert!(text.contains("/dev/sda3 [previous run]"));
+        assert!(text.contains("Failed step:\n  Install (install-packages) 42 packages"));
+        assert!(text.contains("\n    moss: no such package"));
+    }
+}
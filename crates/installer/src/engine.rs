@@ -4,7 +4,10 @@
 
 //! Concrete implementation of the isntaller
 
-use std::path::Path;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
 
 use system::{
     disk::{self, Disk},
@@ -14,11 +17,16 @@ use thiserror::Error;
 use topology::disk::Builder;
 
 use crate::{
+    index::{self, Index},
+    model::duplicate_mountpoint,
+    selections,
     steps::{
-        self, AddRepo, BindMount, Cleanup, Context, CreateAccount, EmitFstab, FormatPartition, FstabEntry,
-        InstallPackages, MountPartition, SetLocale, SetPassword, Step, Unmount,
+        self, AddRepo, BindMount, Cleanup, CloseEncrypted, Context, CreateAccount, CreateSubvolumes, CreateSwapfile,
+        EmitCrypttab, EmitFstab, EncryptPartition, FormatPartition, FormatSwap, FstabEntry, IndexStones,
+        InstallBootloader, InstallPackages, MountPartition, OpenEncrypted, RecordInstall, SetLocale, SetPassword, Step,
+        Unmount,
     },
-    BootPartition, Model, SystemPartition,
+    BootPartition, Filesystem, Manifest, Model, Swap, SystemPartition,
 };
 
 #[derive(Debug, Error)]
@@ -26,12 +34,42 @@ pub enum Error {
     #[error("disk: {0}")]
     Disk(#[from] disk::Error),
 
+    #[error("io: {0}")]
+    IO(#[from] std::io::Error),
+
     #[error("locale: {0}")]
     Locale(#[from] locale::Error),
 
     #[error("missing mandatory partition: {0}")]
     MissingPartition(&'static str),
 
+    #[error("encryption requested without a LUKS UUID")]
+    MissingLuksUuid,
+
+    #[error("{} is mounted more than once", .0.display())]
+    DuplicateMountpoint(PathBuf),
+
+    #[error("btrfs layout has no subvolume for `/`")]
+    MissingRootSubvolume,
+
+    #[error("no enabled repositories to install from")]
+    NoRepositories,
+
+    #[error("packages can only be indexed for a local (file://) repository, not {0}")]
+    LocalRepository(String),
+
+    #[error("cannot install offline from remote repositories")]
+    RemoteRepositories,
+
+    #[error("packages not available: {}", .0.iter().cloned().collect::<Vec<_>>().join(", "))]
+    Unavailable(BTreeSet<String>),
+
+    #[error("index: {0}")]
+    Index(#[from] index::Error),
+
+    #[error("selections: {0}")]
+    Selections(#[from] selections::Error),
+
     #[error("steps: {0}")]
     Steps(#[from] steps::Error),
 
@@ -53,6 +91,9 @@ pub struct Installer {
 
     /// System partitions
     system_parts: Vec<SystemPartition>,
+
+    /// Disks available for installation
+    disks: Vec<Disk>,
 }
 
 impl Installer {
@@ -74,15 +115,20 @@ impl Installer {
             }
         };
 
-        // Exclude parent block devices related to `/` partition
         let parents = root_nodes
             .iter()
             .filter_map(|n| probe.get_device_parent(n))
             .collect::<Vec<_>>();
 
+        // Exclude parent block devices related to `/` partition
+        let disks = disks
+            .into_iter()
+            .filter(|d| !parents.contains(&d.path))
+            .collect::<Vec<_>>();
+
         let mut boot_parts = vec![];
         let mut system_parts = vec![];
-        for disk in disks.iter().filter(|d| !parents.iter().any(|r| *r == d.path)) {
+        for disk in disks.iter() {
             let parts = match disk.partitions() {
                 Ok(parts) => parts,
                 Err(e) => {
@@ -94,7 +140,7 @@ impl Installer {
             // Exclude partitions related to `/` partition
             let parts = parts
                 .into_iter()
-                .filter(|p| !root_nodes.iter().any(|r| *r == p.path))
+                .filter(|p| !root_nodes.contains(&p.path))
                 .collect::<Vec<_>>();
             if let Some(esp) = parts
                 .iter()
@@ -109,16 +155,18 @@ impl Installer {
                     esp,
                     xbootldr,
                     parent_desc: disk.to_string(),
+                    format: false,
                 })
             }
             let others = parts
                 .iter()
-                .filter(|p| matches!(p.kind, disk::PartitionKind::Regular))
+                .filter(|p| matches!(p.kind, disk::PartitionKind::Root | disk::PartitionKind::Regular))
                 .cloned();
             system_parts.extend(others.map(|p| SystemPartition {
                 partition: p,
                 mountpoint: None,
                 parent_desc: disk.to_string(),
+                filesystem: None,
             }));
         }
 
@@ -126,6 +174,7 @@ impl Installer {
             locale_registry,
             system_parts,
             boot_parts,
+            disks,
         })
     }
 
@@ -154,6 +203,60 @@ impl Installer {
         &self.system_parts
     }
 
+    /// Return references to the disks available for installation
+    pub fn disks(&self) -> &[Disk] {
+        &self.disks
+    }
+
+    /// Every package installed for the model: the resolved selections, plus
+    /// the fsck tools of its filesystems and anything its swap needs
+    pub fn packages(&self, model: &Model<'_>) -> BTreeSet<String> {
+        let others = model
+            .partitions
+            .iter()
+            .filter(|p| p.mountpoint.as_ref().is_some_and(|m| m != "/"))
+            .filter_map(|p| p.filesystem);
+
+        let mut packages = model.resolved_packages();
+        packages.extend(
+            [model.rootfs_type, Filesystem::VFAT]
+                .into_iter()
+                .chain(others)
+                .map(|f| f.fsck_package().to_string()),
+        );
+        if let Some(Swap::Zram) = model.swap.as_ref() {
+            packages.insert("zram-generator".into());
+        }
+        packages
+    }
+
+    /// Check every package of the model is available before any disk is
+    /// touched, reporting those of the selected groups by group. Only possible
+    /// when all repositories are local, and required when installing offline.
+    pub fn preflight<'a>(
+        &self,
+        model: &Model<'_>,
+        selections: &selections::Manager,
+        groups: impl IntoIterator<Item = &'a str>,
+        offline: bool,
+    ) -> Result<(), Error> {
+        let Some(index) = Index::from_repositories(&model.repositories)? else {
+            if offline {
+                return Err(Error::RemoteRepositories);
+            }
+            log::warn!("Not checking package availability, as not every repository is local");
+            return Ok(());
+        };
+        selections.validate(groups, &index)?;
+
+        let packages = self.packages(model);
+        let missing = index.missing(packages.iter().map(String::as_str));
+        if !missing.is_empty() {
+            return Err(Error::Unavailable(missing.into_iter().map(String::from).collect()));
+        }
+        Ok(())
+    }
+
     /// build the model into a set of install steps
     pub fn compile_to_steps<'a>(
         &'a self,
@@ -164,6 +267,10 @@ impl Installer {
         let mut c: Vec<Cleanup> = vec![];
         let boot_part = &model.boot_partition.esp;
 
+        if let Some(mountpoint) = duplicate_mountpoint(model.mountpoints()) {
+            return Err(Error::DuplicateMountpoint(mountpoint.to_path_buf()));
+        }
+
         let root_partition = model
             .partitions
             .iter()
@@ -176,52 +283,169 @@ impl Installer {
             })
             .ok_or(Error::MissingPartition("/"))?;
 
+        // Encrypt `/` first so that the filesystem is created within the container
+        let encryption = if let Some(passphrase) = model.luks_passphrase.as_ref() {
+            let uuid = model.luks_uuid.clone().ok_or(Error::MissingLuksUuid)?;
+            let name = format!("luks-{uuid}");
+            s.push(Step::encrypt(EncryptPartition {
+                device: root_partition.partition.path.clone(),
+                uuid: uuid.clone(),
+                passphrase: passphrase.clone(),
+            }));
+            s.push(Step::open_encrypted(OpenEncrypted {
+                device: root_partition.partition.path.clone(),
+                name: name.clone(),
+                passphrase: passphrase.clone(),
+            }));
+            c.push(Cleanup::close_encrypted(CloseEncrypted { name: name.clone() }));
+            Some((uuid, name))
+        } else {
+            None
+        };
+        let root_device = match encryption.as_ref() {
+            Some((_, name)) => PathBuf::from("/dev/mapper").join(name),
+            None => root_partition.partition.path.clone(),
+        };
+
+        // btrfs roots are split into subvolumes, `/` itself being one of them
+        let subvolumes = if model.rootfs_type == Filesystem::BTRFS {
+            Some(&model.btrfs_subvolumes)
+        } else {
+            None
+        };
+        let root_subvolume = match subvolumes {
+            Some(subvolumes) => Some(
+                subvolumes
+                    .iter()
+                    .find(|v| v.mountpoint == Path::new("/"))
+                    .ok_or(Error::MissingRootSubvolume)?,
+            ),
+            None => None,
+        };
+
         // Must format and mount `/` before we can add more mounts
         s.push(Step::format(FormatPartition {
-            partition: &root_partition.partition,
-            filesystem: model.rootfs_type.clone(),
+            device: root_device.clone(),
+            filesystem: model.rootfs_type,
+            label: None,
         }));
+        if let Some(subvolumes) = subvolumes {
+            s.push(Step::create_subvolumes(CreateSubvolumes {
+                device: root_device.clone(),
+                mountpoint: context.root().clone(),
+                names: subvolumes.iter().map(|v| v.name.clone()).collect(),
+            }));
+        }
         s.push(Step::mount(MountPartition {
-            partition: &root_partition.partition,
+            device: root_device.clone(),
             mountpoint: context.root().clone(),
+            options: root_subvolume.map(|v| v.mount_options()),
         }));
         c.push(Cleanup::unmount(Unmount {
             mountpoint: context.root().clone(),
         }));
 
-        // Mount the ESP
-        s.push(Step::mount(MountPartition {
-            partition: boot_part,
-            mountpoint: context.root().join("efi"),
-        }));
-        c.push(Cleanup::unmount(Unmount {
-            mountpoint: context.root().join("efi"),
-        }));
+        // All other system partitions with a mountpoint
+        let others = model
+            .partitions
+            .iter()
+            .filter(|p| p.mountpoint.as_ref().is_some_and(|m| m != "/"))
+            .collect::<Vec<_>>();
 
-        // Mount xbootldr at `/boot` if present
+        for partition in others.iter() {
+            if let Some(filesystem) = partition.filesystem {
+                s.push(Step::format(FormatPartition {
+                    device: partition.partition.path.clone(),
+                    filesystem,
+                    label: None,
+                }));
+            }
+        }
+
+        if let Some(Swap::Partition(partition)) = model.swap.as_ref() {
+            s.push(Step::format_swap(FormatSwap {
+                device: partition.partition.path.clone(),
+            }));
+        }
+
+        // Freshly format the boot partitions if requested
+        if model.boot_partition.format {
+            s.push(Step::format(FormatPartition {
+                device: boot_part.path.clone(),
+                filesystem: Filesystem::VFAT,
+                label: Some("ESP".into()),
+            }));
+            if let Some(xbootldr) = model.boot_partition.xbootldr.as_ref() {
+                s.push(Step::format(FormatPartition {
+                    device: xbootldr.path.clone(),
+                    filesystem: Filesystem::VFAT,
+                    label: Some("XBOOTLDR".into()),
+                }));
+            }
+        }
+
+        // Remaining subvolumes and partitions, the ESP and XBOOTLDR (at `/boot`
+        // if present), all parents before children
+        let mut mounts = others
+            .iter()
+            .filter_map(|p| {
+                p.mountpoint
+                    .as_ref()
+                    .map(|m| (Path::new(m), p.partition.path.clone(), None))
+            })
+            .collect::<Vec<_>>();
+        if let Some(subvolumes) = subvolumes {
+            mounts.extend(
+                subvolumes
+                    .iter()
+                    .filter(|v| v.mountpoint != Path::new("/"))
+                    .map(|v| (v.mountpoint.as_path(), root_device.clone(), Some(v.mount_options()))),
+            );
+        }
+        mounts.push((Path::new("/efi"), boot_part.path.clone(), None));
         if let Some(xbootldr) = model.boot_partition.xbootldr.as_ref() {
+            mounts.push((Path::new("/boot"), xbootldr.path.clone(), None));
+        }
+        mounts.sort_by_key(|(m, ..)| m.components().count());
+        for (mountpoint, device, options) in mounts {
+            let mountpoint = context.root().join(mountpoint.strip_prefix("/").unwrap_or(mountpoint));
             s.push(Step::mount(MountPartition {
-                partition: xbootldr,
-                mountpoint: context.root().join("boot"),
-            }));
-            c.push(Cleanup::unmount(Unmount {
-                mountpoint: context.root().join("boot"),
+                device,
+                mountpoint: mountpoint.clone(),
+                options,
             }));
-        };
+            c.push(Cleanup::unmount(Unmount { mountpoint }));
+        }
 
         // Populate vfs bind mounts
         let (mounts, unmounts) = self.create_vfs_mounts(context.root());
         s.extend(mounts);
         c.extend(unmounts);
 
-        // HAX:
-        s.push(Step::add_repo(AddRepo {
-            uri: "https://dev.serpentos.com/volatile/x86_64/stone.index".into(),
-            name: "unstable".into(),
-            priority: 0,
-        }));
+        let repositories = model.repositories.iter().filter(|r| r.enabled).collect::<Vec<_>>();
+        if repositories.is_empty() {
+            return Err(Error::NoRepositories);
+        }
+        for repository in repositories {
+            if let Some(stones) = repository.stones.as_ref() {
+                let index = repository.uri.strip_prefix("file://").map(PathBuf::from);
+                let Some(directory) = index.as_deref().and_then(Path::parent) else {
+                    return Err(Error::LocalRepository(repository.uri.clone()));
+                };
+                s.push(Step::index_stones(IndexStones {
+                    stones: stones.clone(),
+                    directory: directory.to_path_buf(),
+                }));
+            }
+            s.push(Step::add_repo(AddRepo {
+                uri: repository.uri.clone(),
+                name: repository.name.clone(),
+                priority: repository.priority,
+            }));
+        }
+
         s.push(Step::install_packages(InstallPackages {
-            names: model.packages.iter().cloned().collect::<Vec<_>>(),
+            names: self.packages(model).into_iter().collect::<Vec<_>>(),
         }));
 
         // Update any passwords
@@ -247,15 +471,92 @@ impl Installer {
         // Ensure we get a machine-id..
         s.push(Step::set_machine_id());
 
+        // Swapfile (in its own subvolume on btrfs) or zram
+        let swapfile = match model.swap.as_ref() {
+            Some(Swap::File { size }) => {
+                let path = if model.rootfs_type == Filesystem::BTRFS {
+                    PathBuf::from("swap/swapfile")
+                } else {
+                    PathBuf::from("swapfile")
+                };
+                s.push(Step::create_swapfile(CreateSwapfile {
+                    path: path.clone(),
+                    size: *size,
+                    filesystem: model.rootfs_type,
+                }));
+                Some(path)
+            }
+            Some(Swap::Zram) => {
+                s.push(Step::configure_zram());
+                None
+            }
+            _ => None,
+        };
+
         // Write the fstab
-        let fstab = EmitFstab::default().with_entries([
-            FstabEntry::Comment(format!(
-                "{} at time of installation",
-                root_partition.partition.path.display()
-            )),
-            FstabEntry::try_from(root_partition)?,
-        ]);
-        s.push(Step::emit_fstab(fstab));
+        let other_entries = others
+            .iter()
+            .map(|p| match p.filesystem.as_ref() {
+                Some(filesystem) => FstabEntry::with_filesystem(p, filesystem),
+                None => FstabEntry::try_from(*p),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let root_source = match encryption.as_ref() {
+            Some(_) => root_device.display().to_string(),
+            None => format!("PARTUUID={}", root_partition.partition.uuid),
+        };
+        let mut entries = vec![FstabEntry::Comment(format!(
+            "{} at time of installation",
+            root_partition.partition.path.display()
+        ))];
+        if let Some(subvolumes) = subvolumes {
+            entries.extend(subvolumes.iter().map(|v| FstabEntry::with_subvolume(&root_source, v)));
+        } else {
+            entries.push(
+                FstabEntry::with_filesystem(root_partition, &model.rootfs_type)?.with_source(root_source.clone()),
+            );
+        }
+        entries.extend(other_entries);
+        match model.swap.as_ref() {
+            Some(Swap::Partition(partition)) => {
+                entries.push(FstabEntry::swap(format!("PARTUUID={}", partition.partition.uuid)))
+            }
+            Some(Swap::File { .. }) => {
+                if let Some(path) = swapfile {
+                    entries.push(FstabEntry::swap(format!("/{}", path.display())));
+                }
+            }
+            _ => {}
+        }
+        s.push(Step::emit_fstab(EmitFstab::default().with_entries(entries)));
+
+        let luks_uuid = match encryption {
+            Some((uuid, name)) => {
+                s.push(Step::emit_crypttab(EmitCrypttab {
+                    name,
+                    uuid: uuid.clone(),
+                }));
+                Some(uuid)
+            }
+            None => None,
+        };
+
+        // systemd-boot + entries for the installed kernel(s)
+        s.push(Step::install_bootloader(InstallBootloader {
+            xbootldr: model.boot_partition.xbootldr.is_some(),
+            root: root_source,
+            luks_uuid,
+            subvolume: root_subvolume.map(|v| v.name.clone()),
+            efi_variables: !model.chroot_only,
+        }));
+
+        // Keep a replayable record of how this system was installed
+        s.push(Step::record_install(RecordInstall {
+            manifest: Manifest::from(model),
+        }));
+
+        // Last, so the log is as complete as possible
+        s.push(Step::save_log());
 
         // Get the sync call in for unmounts
         c.push(Cleanup::sync_fs());
@@ -288,3 +589,182 @@ impl Installer {
             .collect::<(Vec<_>, Vec<_>)>()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use system::{disk::Partition, locale};
+
+    use super::{Error, Installer};
+    use crate::{
+        journal::{Journal, Prepared},
+        plan::DryRun,
+        selections::{self, Group},
+        Account, BootPartition, Filesystem, Manifest, Model, Repository, Subvolume, Swap, SystemPartition,
+    };
+
+    /// Nothing probed, which compiling a model doesn't need
+    fn installer() -> Installer {
+        Installer {
+            locale_registry: locale::Registry::new().unwrap(),
+            boot_parts: vec![],
+            system_parts: vec![],
+            disks: vec![],
+        }
+    }
+
+    fn model() -> Model<'static> {
+        let partition = |path: &str, uuid: &str| Partition {
+            path: path.into(),
+            uuid: uuid.into(),
+            ..Default::default()
+        };
+        Model {
+            accounts: BTreeSet::from([
+                Account::root().with_password("hunter2"),
+                Account::new("user").with_password("hunter2"),
+            ]),
+            boot_partition: BootPartition {
+                esp: partition("/dev/sda1", "e3c9e316-0b5c-4db8-817d-f92df00215ae"),
+                xbootldr: Some(partition("/dev/sda2", "bc13c2ff-59e6-4262-a352-b275fd6f7172")),
+                parent_desc: String::new(),
+                format: true,
+            },
+            partitions: vec![SystemPartition {
+                partition: partition("/dev/sda3", "4f68bce3-e8cd-4db1-96e7-fbcaf984b709"),
+                mountpoint: Some("/".into()),
+                parent_desc: String::new(),
+                filesystem: None,
+            }],
+            locale: None,
+            timezone: Some("Europe/London".into()),
+            groups: vec![],
+            packages: BTreeSet::from(["moss".into()]),
+            additions: BTreeSet::new(),
+            exclusions: BTreeSet::new(),
+            repositories: Repository::defaults(),
+            rootfs_type: Filesystem::BTRFS,
+            btrfs_subvolumes: Subvolume::default_layout(),
+            swap: Some(Swap::Zram),
+            luks_passphrase: Some("secret".into()),
+            luks_uuid: Some("4c5b2f0e-4d4a-4b1e-9d55-2f3e0b7c9a61".into()),
+            chroot_only: false,
+        }
+    }
+
+    #[test]
+    fn test_packages() {
+        let model = model();
+        let mut home = model.partitions[0].clone();
+        home.mountpoint = Some("/home".into());
+        home.filesystem = Some(Filesystem::EXT4);
+        let model = Model {
+            partitions: vec![model.partitions[0].clone(), home],
+            packages: BTreeSet::from(["moss".into(), "binary(nano)".into()]),
+            exclusions: BTreeSet::from(["moss".into(), "nano".into()]),
+            ..model
+        };
+
+        let packages = installer().packages(&model);
+        let expected = [Filesystem::BTRFS, Filesystem::VFAT, Filesystem::EXT4]
+            .into_iter()
+            .map(|f| f.fsck_package())
+            .chain(["zram-generator"])
+            .map(String::from)
+            .collect::<BTreeSet<_>>();
+        assert_eq!(packages, expected);
+    }
+
+    #[test]
+    fn test_preflight_remote() {
+        let installer = installer();
+        let selections = selections::Manager::new();
+
+        // Remote repositories can't be checked, which only matters offline
+        assert!(installer.preflight(&model(), &selections, [], false).is_ok());
+        assert!(matches!(
+            installer.preflight(&model(), &selections, [], true),
+            Err(Error::RemoteRepositories)
+        ));
+    }
+
+    #[test]
+    fn test_resume_same_model() {
+        let installer = installer();
+        let model = model();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.json");
+        let context = DryRun::new("/tmp/lichen");
+
+        let (_, steps) = installer.compile_to_steps(&model, &context).unwrap();
+        let mut journal = Journal::open(&path, &model).unwrap();
+        journal.prepare(&model).unwrap();
+        for (index, step) in steps.iter().enumerate() {
+            journal.record(index, step).unwrap();
+        }
+
+        let (_, steps) = installer.compile_to_steps(&model, &context).unwrap();
+        let journal = Journal::open(&path, &model).unwrap();
+        assert_eq!(journal.prepared(), Some(&Prepared::from(&model)));
+        assert_eq!(journal.len(), steps.len());
+        assert!(steps
+            .iter()
+            .enumerate()
+            .all(|(index, step)| journal.is_completed(index, step)));
+
+        // Another LUKS UUID means the encrypted partition is no longer the same
+        let model = Model {
+            luks_uuid: Some("0d1d8c9c-3f0e-4c4b-a7a6-5c0c1f7e2b3a".into()),
+            ..model
+        };
+        let mut journal = Journal::open(&path, &model).unwrap();
+        assert!(journal.is_empty());
+        journal.prepare(&model).unwrap();
+        assert!(journal.is_empty());
+    }
+
+    #[test]
+    fn test_replay() {
+        let installer = installer();
+        let selections = selections::Manager::new().with_groups([Group {
+            name: "develop".into(),
+            required: vec!["moss".into(), "binary(nano)".into()],
+            ..Default::default()
+        }]);
+        let model = model();
+        let model = Model {
+            groups: vec!["develop".into()],
+            packages: selections.selections_with(["develop"]).unwrap(),
+            additions: BTreeSet::from(["vim".into()]),
+            exclusions: BTreeSet::from(["nano".into(), "vim".into()]),
+            ..model
+        };
+
+        let replay = |manifest: &Manifest| {
+            manifest
+                .to_model(
+                    &installer,
+                    &[],
+                    &selections,
+                    model.boot_partition.clone(),
+                    model.partitions.clone(),
+                )
+                .unwrap()
+        };
+        let replayed = replay(&Manifest::from(&model));
+        assert_eq!(replayed.groups, model.groups);
+        assert_eq!(replayed.packages, model.packages);
+        assert_eq!(replayed.additions, model.additions);
+        assert_eq!(replayed.exclusions, model.exclusions);
+        assert_eq!(replayed.resolved_packages(), BTreeSet::from(["moss".into()]));
+
+        // Exclusions may also apply to the listed packages alone
+        let manifest = Manifest {
+            groups: vec![],
+            packages: model.resolved_packages().union(&model.exclusions).cloned().collect(),
+            ..Manifest::from(&model)
+        };
+        assert_eq!(replay(&manifest).resolved_packages(), BTreeSet::from(["moss".into()]));
+    }
+}
@@ -0,0 +1,240 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Supported filesystems
+//!
+//! Every filesystem the installer can create is described once in the
+//! [`REGISTRY`], covering how to format it and how it should appear
+//! in the fstab of the installed system.
+
+use std::{fmt::Display, path::Path, process::Command, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unknown filesystem: {0}")]
+    Unknown(String),
+}
+
+/// Description of a filesystem we know how to create
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Filesystem {
+    /// Name as used by `mount -t` and in the fstab
+    name: &'static str,
+
+    /// The mkfs program
+    mkfs: &'static str,
+
+    /// Arguments always passed to mkfs (i.e. force)
+    mkfs_args: &'static [&'static str],
+
+    /// Arguments to set a label, `{}` is replaced with the label
+    label_args: &'static [&'static str],
+
+    /// Arguments to set a UUID, `{}` is replaced with the UUID. Empty if unsupported.
+    uuid_args: &'static [&'static str],
+
+    /// Default mount options
+    mount_options: &'static str,
+
+    /// fstab `pass` value when used as `/`. Any non-zero value becomes `2` elsewhere.
+    fstab_pass: u8,
+
+    /// Package (provider) supplying the fsck binary
+    fsck_package: &'static str,
+
+    /// Suitable for the system root
+    rootfs: bool,
+}
+
+impl Filesystem {
+    pub const EXT4: Self = Self {
+        name: "ext4",
+        mkfs: "mkfs.ext4",
+        mkfs_args: &["-F"],
+        label_args: &["-L", "{}"],
+        uuid_args: &["-U", "{}"],
+        mount_options: "rw,errors=remount-ro",
+        fstab_pass: 1,
+        fsck_package: "sysbinary(fsck.ext4)",
+        rootfs: true,
+    };
+
+    pub const XFS: Self = Self {
+        name: "xfs",
+        mkfs: "mkfs.xfs",
+        mkfs_args: &["-f"],
+        label_args: &["-L", "{}"],
+        uuid_args: &["-m", "uuid={}"],
+        mount_options: "rw",
+        fstab_pass: 0,
+        fsck_package: "sysbinary(fsck.xfs)",
+        rootfs: true,
+    };
+
+    pub const F2FS: Self = Self {
+        name: "f2fs",
+        mkfs: "mkfs.f2fs",
+        mkfs_args: &["-f"],
+        label_args: &["-l", "{}"],
+        uuid_args: &["-U", "{}"],
+        mount_options: "rw",
+        fstab_pass: 1,
+        fsck_package: "sysbinary(fsck.f2fs)",
+        rootfs: true,
+    };
+
+    pub const BTRFS: Self = Self {
+        name: "btrfs",
+        mkfs: "mkfs.btrfs",
+        mkfs_args: &["-f"],
+        label_args: &["-L", "{}"],
+        uuid_args: &["-U", "{}"],
+        mount_options: "rw,compress=zstd",
+        fstab_pass: 0,
+        fsck_package: "sysbinary(fsck.btrfs)",
+        rootfs: true,
+    };
+
+    pub const VFAT: Self = Self {
+        name: "vfat",
+        mkfs: "mkfs.fat",
+        mkfs_args: &["-F", "32"],
+        label_args: &["-n", "{}"],
+        uuid_args: &[],
+        mount_options: "rw,umask=0077",
+        fstab_pass: 2,
+        fsck_package: "sysbinary(fsck.fat)",
+        rootfs: false,
+    };
+
+    /// Every filesystem known to the installer
+    pub fn all() -> &'static [Filesystem] {
+        REGISTRY
+    }
+
+    /// Filesystems suitable for the system root
+    pub fn rootfs() -> impl Iterator<Item = &'static Filesystem> {
+        REGISTRY.iter().filter(|f| f.rootfs)
+    }
+
+    /// Whether this filesystem can be used for `/`
+    pub fn is_rootfs(&self) -> bool {
+        self.rootfs
+    }
+
+    /// Name as used by `mount -t` and in the fstab
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Default mount options
+    pub fn mount_options(&self) -> &'static str {
+        self.mount_options
+    }
+
+    /// fstab `pass` value for the given mountpoint
+    pub fn fstab_pass(&self, mountpoint: &str) -> u8 {
+        match self.fstab_pass {
+            0 => 0,
+            pass if mountpoint == "/" => pass,
+            _ => 2,
+        }
+    }
+
+    /// Package providing the fsck binary for this filesystem
+    pub fn fsck_package(&self) -> &'static str {
+        self.fsck_package
+    }
+
+    /// Build the mkfs command for the given device
+    pub fn mkfs_command(&self, device: &Path, label: Option<&str>, uuid: Option<&str>) -> Command {
+        let mut cmd = Command::new(self.mkfs);
+        cmd.args(self.mkfs_args);
+        if let Some(label) = label {
+            cmd.args(self.label_args.iter().map(|a| a.replace("{}", label)));
+        }
+        if let Some(uuid) = uuid {
+            cmd.args(self.uuid_args.iter().map(|a| a.replace("{}", uuid)));
+        }
+        cmd.arg(device);
+        cmd
+    }
+}
+
+/// The filesystem registry: add new filesystems here
+const REGISTRY: &[Filesystem] = &[
+    Filesystem::XFS,
+    Filesystem::F2FS,
+    Filesystem::EXT4,
+    Filesystem::BTRFS,
+    Filesystem::VFAT,
+];
+
+impl FromStr for Filesystem {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = match s.to_lowercase().as_str() {
+            "fat32" | "fat" => "vfat".to_string(),
+            name => name.to_string(),
+        };
+        REGISTRY
+            .iter()
+            .find(|f| f.name == name)
+            .copied()
+            .ok_or_else(|| Error::Unknown(s.to_string()))
+    }
+}
+
+impl Display for Filesystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl<'de> Deserialize<'de> for Filesystem {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Filesystem {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Filesystem;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!("XFS".parse::<Filesystem>().unwrap(), Filesystem::XFS);
+        assert_eq!("fat32".parse::<Filesystem>().unwrap(), Filesystem::VFAT);
+        assert!("zfs".parse::<Filesystem>().is_err());
+        assert!(Filesystem::rootfs().all(|f| *f != Filesystem::VFAT));
+    }
+
+    #[test]
+    fn test_mkfs_command() {
+        let cmd = Filesystem::XFS.mkfs_command(Path::new("/dev/sda3"), Some("root"), Some("1234"));
+        let args = cmd.get_args().map(|a| a.to_string_lossy()).collect::<Vec<_>>();
+        assert_eq!(cmd.get_program(), "mkfs.xfs");
+        assert_eq!(args, ["-f", "-L", "root", "-m", "uuid=1234", "/dev/sda3"]);
+    }
+
+    #[test]
+    fn test_fstab_pass() {
+        assert_eq!(Filesystem::EXT4.fstab_pass("/"), 1);
+        assert_eq!(Filesystem::EXT4.fstab_pass("/home"), 2);
+        assert_eq!(Filesystem::BTRFS.fstab_pass("/"), 0);
+    }
+}
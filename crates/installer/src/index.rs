@@ -0,0 +1,422 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Package availability from moss stone files
+//!
+//! Reads just enough of the stone format to know which packages (and
+//! providers) a repository index, or a directory of `.stone` packages,
+//! offers. Only the metadata payloads are decoded: content is skipped.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use fs_err as fs;
+use thiserror::Error;
+
+use crate::{
+    steps::{self, Context},
+    Repository,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    IO(#[from] io::Error),
+
+    #[error("not a stone file")]
+    InvalidMagic,
+
+    #[error("unsupported stone version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("unsupported payload compression {0}")]
+    UnsupportedCompression(u8),
+
+    #[error("malformed metadata record")]
+    MalformedRecord,
+
+    #[error("downloading {uri} failed: {source}")]
+    Download { uri: String, source: Box<steps::Error> },
+}
+
+const MAGIC: &[u8; 4] = b"\0mos";
+
+/// Payload kind holding package metadata
+const PAYLOAD_META: u8 = 1;
+
+/// Payload kind holding the (concatenated) files of a package
+const PAYLOAD_CONTENT: u8 = 2;
+
+const COMPRESSION_NONE: u8 = 1;
+const COMPRESSION_ZSTD: u8 = 2;
+
+/// Metadata tags of interest
+const TAG_NAME: u16 = 1;
+const TAG_VERSION: u16 = 3;
+const TAG_PROVIDES: u16 = 9;
+const TAG_PACKAGE_SIZE: u16 = 16;
+
+/// Metadata value kinds of interest
+const KIND_UINT64: u8 = 8;
+const KIND_STRING: u8 = 9;
+const KIND_PROVIDER: u8 = 11;
+
+/// Provider kinds, in the order moss encodes them
+const PROVIDER_KINDS: [&str; 9] = [
+    "name",
+    "soname",
+    "pkgconfig",
+    "interpreter",
+    "cmake",
+    "python",
+    "binary",
+    "sysbinary",
+    "pkgconfig32",
+];
+
+/// A package offered by a repository
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Package {
+    pub name: String,
+    pub version: Option<String>,
+
+    /// Everything the package provides, i.e. `binary(bash)`
+    pub providers: BTreeSet<String>,
+
+    /// Size of the `.stone`, if known
+    pub size: Option<u64>,
+
+    /// Size of the files once installed, only known when read from the `.stone`
+    pub installed_size: Option<u64>,
+}
+
+impl Package {
+    /// Best estimate of the installed size, falling back to the `.stone` size
+    pub fn estimated_size(&self) -> u64 {
+        self.installed_size.or(self.size).unwrap_or_default()
+    }
+
+    /// Can this package satisfy `name`, which is a package name or provider?
+    pub fn provides(&self, name: &str) -> bool {
+        self.name == name || self.providers.contains(name)
+    }
+}
+
+/// All packages offered by a repository
+#[derive(Debug, Clone, Default)]
+pub struct Index {
+    packages: Vec<Package>,
+}
+
+impl Index {
+    /// Where the indexes of remote repositories are downloaded to, on the host
+    pub const CACHE_DIR: &'static str = "/run/lichen/indexes";
+
+    /// Read a repository index (`stone.index`) or a single `.stone` package
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut file = io::BufReader::new(fs::File::open(path.as_ref())?);
+        Ok(Self {
+            packages: read_packages(&mut file)?,
+        })
+    }
+
+    /// Read every `.stone` package in the directory
+    pub fn from_stones(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut packages = vec![];
+        for path in stones_in(dir)? {
+            packages.extend(Self::from_path(path)?.packages);
+        }
+        Ok(Self { packages })
+    }
+
+    /// Combined index of the enabled repositories, if they are all local.
+    /// Remote repositories would need fetching, so nothing can be checked.
+    pub fn from_repositories<'a, I: IntoIterator<Item = &'a Repository>>(
+        repositories: I,
+    ) -> Result<Option<Self>, Error> {
+        let mut packages = vec![];
+        for repository in repositories.into_iter().filter(|r| r.enabled) {
+            let index = match (repository.stones.as_ref(), repository.uri.strip_prefix("file://")) {
+                (Some(stones), _) => Self::from_stones(stones)?,
+                (None, Some(path)) => Self::from_path(path)?,
+                (None, None) => return Ok(None),
+            };
+            packages.extend(index.packages);
+        }
+        Ok(Some(Self { packages }))
+    }
+
+    /// Combined index of the enabled repositories, downloading those that are
+    /// remote into `cache` (with `curl`, run through the context). Only needed for
+    /// estimates, as nothing guarantees the repository is the same by the time of installing.
+    pub fn fetch_repositories<'a, 'c, I: IntoIterator<Item = &'a Repository>>(
+        repositories: I,
+        cache: impl AsRef<Path>,
+        context: &'c impl Context<'c>,
+    ) -> Result<Self, Error> {
+        let mut packages = vec![];
+        for repository in repositories.into_iter().filter(|r| r.enabled) {
+            let index = match Self::from_repositories([repository])? {
+                Some(index) => index,
+                None => Self::from_path(download(repository, cache.as_ref(), context)?)?,
+            };
+            packages.extend(index.packages);
+        }
+        Ok(Self { packages })
+    }
+
+    /// All packages in the index
+    pub fn packages(&self) -> impl Iterator<Item = &'_ Package> {
+        self.packages.iter()
+    }
+
+    /// Find a package providing `name`
+    pub fn find(&self, name: &str) -> Option<&Package> {
+        self.packages.iter().find(|p| p.provides(name))
+    }
+
+    /// Estimated installed size of the packages satisfying `names`, counting each package once
+    pub fn estimated_size<'a, I: IntoIterator<Item = &'a str>>(&self, names: I) -> u64 {
+        names
+            .into_iter()
+            .filter_map(|name| self.find(name))
+            .map(|p| (p.name.as_str(), p.estimated_size()))
+            .collect::<BTreeMap<_, _>>()
+            .values()
+            .sum()
+    }
+
+    /// The names (or providers) that no package in the index can satisfy
+    pub fn missing<'a, I: IntoIterator<Item = &'a str>>(&self, names: I) -> BTreeSet<&'a str> {
+        names.into_iter().filter(|name| self.find(name).is_none()).collect()
+    }
+}
+
+impl FromIterator<Package> for Index {
+    fn from_iter<T: IntoIterator<Item = Package>>(iter: T) -> Self {
+        Self {
+            packages: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Sorted paths of the `.stone` files in `dir`
+pub fn stones_in(dir: impl AsRef<Path>) -> Result<Vec<PathBuf>, Error> {
+    let mut stones = fs::read_dir(dir.as_ref())?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "stone") && path.is_file())
+        .collect::<Vec<_>>();
+    stones.sort();
+    Ok(stones)
+}
+
+/// Read all metadata payloads, each describing one package
+fn read_packages<R: Read + Seek>(reader: &mut R) -> Result<Vec<Package>, Error> {
+    let header = read_array::<32>(reader)?;
+    if &header[..4] != MAGIC {
+        return Err(Error::InvalidMagic);
+    }
+    let version = u32::from_be_bytes([header[28], header[29], header[30], header[31]]);
+    if version != 1 {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let num_payloads = u16::from_be_bytes([header[4], header[5]]);
+
+    let mut packages = Vec::<Package>::new();
+    for _ in 0..num_payloads {
+        let header = read_array::<32>(reader)?;
+        let stored_size = u64::from_be_bytes(header[0..8].try_into().expect("8 bytes"));
+        let plain_size = u64::from_be_bytes(header[8..16].try_into().expect("8 bytes"));
+        let num_records = u32::from_be_bytes(header[24..28].try_into().expect("4 bytes"));
+        let (kind, compression) = (header[30], header[31]);
+
+        if kind == PAYLOAD_CONTENT {
+            if let Some(package) = packages.last_mut() {
+                package.installed_size = Some(plain_size);
+            }
+        }
+        if kind != PAYLOAD_META {
+            reader.seek(SeekFrom::Current(stored_size as i64))?;
+            continue;
+        }
+
+        // The size is untrusted, so only allocate for what is actually there
+        let mut stored = vec![];
+        reader.by_ref().take(stored_size).read_to_end(&mut stored)?;
+        if stored.len() as u64 != stored_size {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let plain = match compression {
+            COMPRESSION_NONE => stored,
+            COMPRESSION_ZSTD => zstd::decode_all(stored.as_slice())?,
+            other => return Err(Error::UnsupportedCompression(other)),
+        };
+        packages.push(read_meta(&plain, num_records)?);
+    }
+    Ok(packages)
+}
+
+/// Decode one metadata payload into a package
+fn read_meta(mut data: &[u8], num_records: u32) -> Result<Package, Error> {
+    let mut package = Package::default();
+    for _ in 0..num_records {
+        let header = take(&mut data, 8)?;
+        let length = u32::from_be_bytes(header[0..4].try_into().expect("4 bytes")) as usize;
+        let tag = u16::from_be_bytes([header[4], header[5]]);
+        let kind = header[6];
+        let value = take(&mut data, length)?;
+
+        match (tag, kind) {
+            (TAG_NAME, KIND_STRING) => package.name = string(value),
+            (TAG_VERSION, KIND_STRING) => package.version = Some(string(value)),
+            (TAG_PACKAGE_SIZE, KIND_UINT64) => {
+                package.size = Some(u64::from_be_bytes(
+                    value.try_into().map_err(|_| Error::MalformedRecord)?,
+                ));
+            }
+            (TAG_PROVIDES, KIND_PROVIDER) => {
+                let (provider_kind, name) = value.split_first().ok_or(Error::MalformedRecord)?;
+                let provider_kind = PROVIDER_KINDS
+                    .get(*provider_kind as usize)
+                    .ok_or(Error::MalformedRecord)?;
+                package.providers.insert(format!("{provider_kind}({})", string(name)));
+            }
+            _ => {}
+        }
+    }
+    Ok(package)
+}
+
+/// Split `len` bytes off the front of `data`
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if data.len() < len {
+        return Err(Error::MalformedRecord);
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+/// Strings are stored NUL-terminated
+fn string(value: &[u8]) -> String {
+    String::from_utf8_lossy(value).trim_end_matches('\0').to_owned()
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N], Error> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Download the index of a remote repository, returning where it was saved
+fn download<'c>(repository: &Repository, cache: &Path, context: &'c impl Context<'c>) -> Result<PathBuf, Error> {
+    let path = cache.join(format!("{}.index", repository.name));
+    log::trace!("Downloading {} to {}", repository.uri, path.display());
+    let mut cmd = Command::new("curl");
+    cmd.args(["--fail", "--silent", "--show-error", "--location", "--output"])
+        .arg(&path)
+        .arg(&repository.uri);
+    context
+        .create_dir_all(cache)
+        .and_then(|_| context.run_command_captured(&mut cmd, None))
+        .map_err(|e| Error::Download {
+            uri: repository.uri.clone(),
+            source: Box::new(e),
+        })?;
+    Ok(path)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+
+    use super::{read_packages, Index};
+
+    /// Encode a metadata record
+    fn record(tag: u16, kind: u8, value: &[u8]) -> Vec<u8> {
+        let mut out = (value.len() as u32).to_be_bytes().to_vec();
+        out.extend(tag.to_be_bytes());
+        out.extend([kind, 0]);
+        out.extend(value);
+        out
+    }
+
+    /// A minimal, uncompressed stone with one metadata payload per package
+    pub(crate) fn stone(packages: &[(&str, &[(u8, &str)])]) -> Vec<u8> {
+        let mut out = b"\0mos".to_vec();
+        out.extend((packages.len() as u16).to_be_bytes());
+        out.extend([0, 0, 1, 0, 0, 2, 0, 0, 3, 0, 0, 4, 0, 0, 5, 0, 0, 6, 0, 0, 7]);
+        out.push(3);
+        out.extend(1u32.to_be_bytes());
+
+        for (name, providers) in packages {
+            let mut payload = record(1, 9, format!("{name}\0").as_bytes());
+            payload.extend(record(16, 8, &1024u64.to_be_bytes()));
+            for (kind, provider) in providers.iter() {
+                let mut value = vec![*kind];
+                value.extend(format!("{provider}\0").as_bytes());
+                payload.extend(record(9, 11, &value));
+            }
+            out.extend((payload.len() as u64).to_be_bytes());
+            out.extend((payload.len() as u64).to_be_bytes());
+            out.extend([0; 8]);
+            out.extend((2 + providers.len() as u32).to_be_bytes());
+            out.extend(1u16.to_be_bytes());
+            out.extend([1, 1]);
+            out.extend(payload);
+        }
+        out
+    }
+
+    #[test]
+    fn test_read_index() {
+        let data = stone(&[("bash", &[(6, "bash"), (6, "sh")]), ("linux-desktop", &[])]);
+        let packages = read_packages(&mut Cursor::new(data)).unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].name, "bash");
+        assert_eq!(packages[0].size, Some(1024));
+        assert!(packages[0].provides("binary(sh)"));
+
+        let index = Index { packages };
+        assert_eq!(index.estimated_size(["bash", "binary(sh)", "linux-desktop"]), 2048);
+        assert_eq!(
+            index.missing(["bash", "binary(bash)", "linux-desktop", "binary(zsh)"]),
+            ["binary(zsh)"].into()
+        );
+    }
+
+    #[test]
+    fn test_installed_size() {
+        let mut data = stone(&[("bash", &[])]);
+        // Follow the metadata with an (empty) content payload of 4 KiB once unpacked
+        data[5] = 2;
+        data.extend(0u64.to_be_bytes());
+        data.extend(4096u64.to_be_bytes());
+        data.extend([0; 8]);
+        data.extend(0u32.to_be_bytes());
+        data.extend(1u16.to_be_bytes());
+        data.extend([2, 1]);
+
+        let packages = read_packages(&mut Cursor::new(data)).unwrap();
+        assert_eq!(packages[0].installed_size, Some(4096));
+        assert_eq!(packages[0].estimated_size(), 4096);
+    }
+
+    #[test]
+    fn test_truncated_payload() {
+        let mut data = stone(&[("bash", &[])]);
+        // Claim a metadata payload far larger than the file
+        data[32..40].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(read_packages(&mut Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn test_invalid_magic() {
+        assert!(read_packages(&mut Cursor::new([0u8; 32])).is_err());
+    }
+}
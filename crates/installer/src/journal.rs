@@ -0,0 +1,230 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Resumable installation journal
+//!
+//! The [`crate::driver::Driver`] records every completed step in the journal,
+//! alongside a fingerprint of its inputs. When an installation of the same
+//! plan is re-run after a failure, completed steps whose effect persists
+//! in the target are skipped and the run continues from the failing step.
+//!
+//! A plan is the [`Model`] before storage is prepared, as previewed by
+//! [`crate::manifest::Target::preview`] with placeholder identifiers. The
+//! partitions and LUKS UUID of the installation are recorded as [`Prepared`]
+//! once known, so that a resumed run uses the same ones as the steps it skips.
+//!
+//! Fingerprints are taken from the [`Manifest`] of the plan and the `Debug`
+//! form of each step, neither of which carry secrets (passwords, passphrases).
+
+use std::{
+    iter,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{manifest::Storage, steps::Step, Manifest, Model};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A completed step
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Entry {
+    pub name: String,
+    pub description: String,
+    pub fingerprint: String,
+}
+
+impl From<&Step<'_>> for Entry {
+    fn from(step: &Step<'_>) -> Self {
+        Self {
+            name: step.name().into(),
+            description: step.describe(),
+            fingerprint: fingerprint(&format!("{step:?}")),
+        }
+    }
+}
+
+/// Storage prepared for the installation, and identifiers generated for it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Prepared {
+    /// Partitions by PARTUUID
+    pub storage: Storage,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub luks_uuid: Option<String>,
+}
+
+impl From<&Model<'_>> for Prepared {
+    fn from(model: &Model<'_>) -> Self {
+        let manifest = Manifest::from(model);
+        Self {
+            storage: manifest.storage,
+            luks_uuid: manifest.luks_uuid,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Contents {
+    /// Fingerprint of the plan being installed
+    model: String,
+
+    /// Storage as prepared by a previous run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prepared: Option<Prepared>,
+
+    /// Completed steps, in order of execution
+    completed: Vec<Entry>,
+}
+
+/// Journal of completed steps for one plan
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    contents: Contents,
+}
+
+impl Journal {
+    /// Default location, on the host. Only needs to survive until a reboot.
+    pub const DEFAULT_PATH: &'static str = "/run/lichen/journal.json";
+
+    /// Open the journal for the plan, discarding one left by any other plan
+    pub fn open(path: impl AsRef<Path>, plan: &Model<'_>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+
+        // Whole disk plans only differ in their devices, the PARTUUIDs being placeholders
+        let devices = iter::once(&plan.boot_partition.esp.path)
+            .chain(plan.partitions.iter().map(|p| &p.partition.path))
+            .collect::<Vec<_>>();
+        let model = fingerprint(&serde_json::to_string(&(Manifest::from(plan), devices))?);
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str::<Contents>(&text)
+                .ok()
+                .filter(|c| c.model == model),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let contents = contents.unwrap_or_else(|| Contents {
+            model,
+            prepared: None,
+            completed: vec![],
+        });
+        if !contents.completed.is_empty() {
+            log::info!(
+                "Resuming from {} ({} steps completed)",
+                path.display(),
+                contents.completed.len()
+            );
+        }
+
+        Ok(Self { path, contents })
+    }
+
+    /// Start over, forgetting the prepared storage and all completed steps
+    pub fn reset(&mut self) {
+        self.contents.prepared = None;
+        self.contents.completed.clear();
+    }
+
+    /// Storage prepared by a previous run, to be reused when resuming
+    pub fn prepared(&self) -> Option<&Prepared> {
+        self.contents.prepared.as_ref()
+    }
+
+    /// Record the prepared storage of the model to install. Completed steps
+    /// are forgotten unless a previous run prepared exactly the same.
+    pub fn prepare(&mut self, model: &Model<'_>) -> Result<(), Error> {
+        let prepared = Prepared::from(model);
+        if self.contents.prepared.as_ref() != Some(&prepared) {
+            self.contents.prepared = Some(prepared);
+            self.contents.completed.clear();
+        }
+        self.write()
+    }
+
+    /// Number of steps completed by previous runs
+    pub fn len(&self) -> usize {
+        self.contents.completed.len()
+    }
+
+    /// True if no steps have completed yet
+    pub fn is_empty(&self) -> bool {
+        self.contents.completed.is_empty()
+    }
+
+    /// Did a previous run complete exactly this step, at this position?
+    pub fn is_completed(&self, index: usize, step: &Step<'_>) -> bool {
+        self.contents.completed.get(index) == Some(&Entry::from(step))
+    }
+
+    /// Record the step at `index` as completed, dropping anything recorded after it
+    pub fn record(&mut self, index: usize, step: &Step<'_>) -> Result<(), Error> {
+        self.contents.completed.truncate(index);
+        self.contents.completed.push(Entry::from(step));
+        self.write()
+    }
+
+    /// Persist the journal
+    fn write(&self) -> Result<(), Error> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.contents)?)?;
+        Ok(())
+    }
+
+    /// The installation is complete, so there is nothing to resume
+    pub fn remove(self) -> Result<(), Error> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            res => Ok(res?),
+        }
+    }
+}
+
+/// Stable 64-bit FNV-1a hash, as hex
+fn fingerprint(input: &str) -> String {
+    let hash = input.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::steps::{InstallPackages, Step};
+
+    use super::{fingerprint, Entry};
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint(""), "cbf29ce484222325");
+        assert_eq!(fingerprint("a"), "af63dc4c8601ec8c");
+    }
+
+    #[test]
+    fn test_entry_inputs() {
+        let install = |names: &[&str]| {
+            Step::install_packages(InstallPackages {
+                names: names.iter().map(|n| n.to_string()).collect(),
+            })
+        };
+        assert_eq!(Entry::from(&install(&["moss"])), Entry::from(&install(&["moss"])));
+        assert_ne!(
+            Entry::from(&install(&["moss"])).fingerprint,
+            Entry::from(&install(&["moss", "vim"])).fingerprint
+        );
+    }
+}
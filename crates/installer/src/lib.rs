@@ -9,18 +9,45 @@ mod model;
 pub use model::Model;
 
 mod account;
-pub use account::Account;
+pub use account::{Account, Password};
 
 mod engine;
 pub use engine::Installer;
 
+pub mod filesystem;
+pub use filesystem::Filesystem;
+
+mod btrfs;
+pub use btrfs::Subvolume;
+
+mod swap;
+pub use swap::Swap;
+
+mod repository;
+pub use repository::Repository;
+
 mod partitions;
 pub use partitions::{BootPartition, SystemPartition};
 
 pub mod systemd;
 
-pub use system::locale::Locale;
+pub use system::{disk::Disk, hardware::Hardware, locale::Locale};
 
 pub mod steps;
 
 pub mod selections;
+
+pub mod strategy;
+
+pub mod plan;
+
+pub mod driver;
+
+pub mod journal;
+
+pub mod index;
+
+pub mod offline;
+
+pub mod manifest;
+pub use manifest::Manifest;
@@ -0,0 +1,1081 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Declarative install manifests (answer files)
+//!
+//! A [`Manifest`] describes everything the interactive frontend would
+//! otherwise ask for, and is resolved against the probed [`Installer`]
+//! state into a [`Model`] for unattended installation.
+//!
+//! Every [`Model`] converts back into a manifest, which is recorded in the
+//! installed system so an install can be replayed. Secrets are never
+//! recorded: plain text passwords are replaced by their hash (once set),
+//! and the LUKS passphrase must be supplied again.
+//!
+//! ```toml
+//! filesystem = "xfs"
+//! groups = ["develop", "gnome", "kernel-desktop"]
+//! packages = ["vim"]
+//! exclude = ["nano"]
+//! locale = "en_US.UTF-8"
+//! timezone = "Europe/London"
+//!
+//! [storage]
+//! strategy = "whole-disk"
+//! disk = { serial = "S4EWNX0N123456" }
+//!
+//! [[accounts]]
+//! username = "root"
+//! password-hash = "$6$..."
+//!
+//! [[repositories]]
+//! name = "mirror"
+//! uri = "https://mirror.example.com/volatile/x86_64/stone.index"
+//! ```
+
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use system::{
+    disk::{self, Disk},
+    locale::Locale,
+};
+use thiserror::Error;
+
+use crate::{
+    journal::Journal,
+    model::duplicate_mountpoint,
+    selections,
+    steps::Context,
+    strategy::{self, WholeDisk},
+    Account, BootPartition, Filesystem, Installer, Model, Password, Repository, Subvolume, Swap, SystemPartition,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("toml: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("disk: {0}")]
+    Disk(#[from] disk::Error),
+
+    #[error("strategy: {0}")]
+    Strategy(#[from] strategy::Error),
+
+    #[error("selections: {0}")]
+    Selections(#[from] selections::Error),
+
+    #[error("no disk matches {0}")]
+    NoSuchDisk(Selector),
+
+    #[error("no partition matches {0}")]
+    NoSuchPartition(Selector),
+
+    #[error("{0} cannot be used to select a {1}")]
+    InvalidSelector(Selector, &'static str),
+
+    #[error("unknown locale: {0}")]
+    UnknownLocale(String),
+
+    #[error("unknown timezone: {0}")]
+    UnknownTimezone(String),
+
+    #[error("{} is selected more than once", .0.display())]
+    PartitionReused(PathBuf),
+
+    #[error("manifest has no accounts")]
+    NoAccounts,
+
+    #[error("encryption requested without a LUKS passphrase")]
+    MissingPassphrase,
+
+    #[error("{0} cannot be used for `/`")]
+    NotRootFilesystem(Filesystem),
+
+    #[error("swap file size must be at least 1 MiB")]
+    EmptySwapFile,
+
+    #[error("invalid btrfs subvolume {name:?} for {}", .mountpoint.display())]
+    InvalidSubvolume { name: String, mountpoint: PathBuf },
+
+    #[error("btrfs subvolume {0} is defined more than once")]
+    DuplicateSubvolume(String),
+
+    #[error("{} is mounted more than once", .0.display())]
+    DuplicateMountpoint(PathBuf),
+
+    #[error("no enabled repositories to install from")]
+    NoRepositories,
+
+    #[error("repository {0} is defined more than once")]
+    DuplicateRepository(String),
+}
+
+/// Identifies a disk or partition
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Selector {
+    /// Device node, i.e. `/dev/sda` (symlinks are resolved)
+    Path(PathBuf),
+
+    /// GPT partition UUID (partitions only)
+    Partuuid(String),
+
+    /// Hardware serial number (disks only)
+    Serial(String),
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Selector::Path(path) => write!(f, "path {}", path.display()),
+            Selector::Partuuid(uuid) => write!(f, "PARTUUID {uuid}"),
+            Selector::Serial(serial) => write!(f, "serial {serial}"),
+        }
+    }
+}
+
+impl Selector {
+    /// Does this select the given device node?
+    fn matches_path(path: &Path, candidate: &Path) -> bool {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        path == candidate
+    }
+
+    fn matches_disk(&self, disk: &Disk) -> Result<bool, Error> {
+        match self {
+            Selector::Path(path) => Ok(Self::matches_path(path, &disk.path)),
+            Selector::Serial(serial) => Ok(disk.serial.as_ref() == Some(serial)),
+            Selector::Partuuid(_) => Err(Error::InvalidSelector(self.clone(), "disk")),
+        }
+    }
+
+    fn matches_partition(&self, partition: &disk::Partition) -> Result<bool, Error> {
+        match self {
+            Selector::Path(path) => Ok(Self::matches_path(path, &partition.path)),
+            Selector::Partuuid(uuid) => Ok(partition.uuid.eq_ignore_ascii_case(uuid)),
+            Selector::Serial(_) => Err(Error::InvalidSelector(self.clone(), "partition")),
+        }
+    }
+}
+
+/// Where the system is installed
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "strategy", rename_all = "kebab-case")]
+pub enum Storage {
+    /// Erase and partition an entire disk
+    #[serde(rename_all = "kebab-case")]
+    WholeDisk { disk: Selector },
+
+    /// Use existing partitions
+    #[serde(rename_all = "kebab-case")]
+    Partitions {
+        /// The ESP (XBOOTLDR is found on the same disk)
+        esp: Selector,
+
+        /// Freshly format the ESP (and XBOOTLDR) as FAT32
+        #[serde(default)]
+        format_esp: bool,
+
+        /// Partition for `/`
+        root: Selector,
+
+        /// Additional partitions
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        mounts: Vec<Mount>,
+    },
+}
+
+impl Storage {
+    /// Resolve against the probed disks + partitions
+    pub fn target<'a>(&self, installer: &'a Installer) -> Result<Target<'a>, Error> {
+        match self {
+            Storage::WholeDisk { disk } => {
+                for candidate in installer.disks() {
+                    if disk.matches_disk(candidate)? {
+                        return Ok(Target::WholeDisk(candidate));
+                    }
+                }
+                Err(Error::NoSuchDisk(disk.clone()))
+            }
+            Storage::Partitions {
+                esp,
+                format_esp,
+                root,
+                mounts,
+            } => {
+                let mut boot = None;
+                for candidate in installer.boot_partitions() {
+                    if esp.matches_partition(&candidate.esp)? {
+                        boot = Some(candidate.clone());
+                        break;
+                    }
+                }
+                let mut boot = boot.ok_or_else(|| Error::NoSuchPartition(esp.clone()))?;
+                boot.format = *format_esp;
+
+                let mut root = find_partition(installer, root)?;
+                root.mountpoint = Some("/".into());
+
+                let mut partitions = vec![root];
+                for mount in mounts {
+                    let mut partition = find_partition(installer, &mount.partition)?;
+                    partition.mountpoint = Some(mount.mountpoint.clone());
+                    partition.filesystem = mount.filesystem;
+                    partitions.push(partition);
+                }
+                Ok(Target::Partitions(boot, partitions))
+            }
+        }
+    }
+}
+
+/// An additional partition mounted in the target
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Mount {
+    pub partition: Selector,
+    pub mountpoint: String,
+
+    /// Format with this filesystem, otherwise keep the existing one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filesystem: Option<Filesystem>,
+}
+
+/// Swap configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum SwapConfig {
+    #[serde(rename_all = "kebab-case")]
+    Partition {
+        partition: Selector,
+    },
+
+    #[serde(rename_all = "kebab-case")]
+    File {
+        size_mib: u64,
+    },
+
+    Zram,
+}
+
+/// An account to create (or update, for `root`)
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AccountConfig {
+    pub username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gecos: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+
+    /// crypt(3) password hash, i.e. from `mkpasswd`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+
+    /// Plain text password, hashed by chpasswd in the target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+/// Plain text passwords are stripped
+impl From<&Account> for AccountConfig {
+    fn from(value: &Account) -> Self {
+        Self {
+            username: value.username.clone(),
+            gecos: value.gecos.clone(),
+            shell: (!value.builtin).then(|| value.shell.clone()),
+            password_hash: match value.password.as_ref() {
+                Some(Password::Hashed(hash)) => Some(hash.clone()),
+                _ => None,
+            },
+            password: None,
+        }
+    }
+}
+
+impl From<&AccountConfig> for Account {
+    fn from(value: &AccountConfig) -> Self {
+        let account = if value.username == "root" {
+            Account::root()
+        } else {
+            Account::new(&value.username).with_shell("/usr/bin/bash")
+        };
+        let account = match value.gecos.as_ref() {
+            Some(gecos) => account.with_gecos(gecos),
+            None => account,
+        };
+        let account = match value.shell.as_ref() {
+            Some(shell) => account.with_shell(shell),
+            None => account,
+        };
+        match (value.password_hash.as_ref(), value.password.as_ref()) {
+            (Some(hash), _) => account.with_hashed_password(hash),
+            (None, Some(password)) => account.with_password(password),
+            (None, None) => account,
+        }
+    }
+}
+
+/// Complete unattended install description
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Manifest {
+    pub storage: Storage,
+
+    /// Filesystem for `/`
+    pub filesystem: Filesystem,
+
+    /// Subvolume layout for a btrfs root, defaulting to [`Subvolume::default_layout`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub btrfs_subvolumes: Option<Vec<Subvolume>>,
+
+    /// Encrypt `/` with LUKS2, requires `luks-passphrase`
+    #[serde(default)]
+    pub encrypt: bool,
+
+    /// Passphrase of the LUKS2 container, only used when `encrypt` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub luks_passphrase: Option<String>,
+
+    /// UUID of the LUKS container, generated when omitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub luks_uuid: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swap: Option<SwapConfig>,
+
+    /// Selection group names to install
+    #[serde(default)]
+    pub groups: Vec<String>,
+
+    /// Packages installed in addition to the groups
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub packages: BTreeSet<String>,
+
+    /// Packages removed from the groups, by name or provider name (`bash` removes
+    /// `binary(bash)`). Each must match something, and nothing required by a mandatory group
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub exclude: BTreeSet<String>,
+
+    /// Repositories to install from, defaulting to [`Repository::defaults`]
+    #[serde(default = "Repository::defaults")]
+    pub repositories: Vec<Repository>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+
+    #[serde(default)]
+    pub accounts: Vec<AccountConfig>,
+
+    /// Leave the host firmware's EFI variables alone
+    #[serde(default)]
+    pub chroot_only: bool,
+}
+
+/// The resolved storage target of a manifest
+#[derive(Debug)]
+pub enum Target<'a> {
+    WholeDisk(&'a Disk),
+    Partitions(BootPartition, Vec<SystemPartition>),
+}
+
+impl Target<'_> {
+    /// Prepare the storage, partitioning the disk (through the context) if needed.
+    ///
+    /// This is destructive for [`Target::WholeDisk`].
+    pub fn apply<'c>(self, context: &'c impl Context<'c>) -> Result<(BootPartition, Vec<SystemPartition>), Error> {
+        match self {
+            Target::WholeDisk(disk) => {
+                let (boot, root) = WholeDisk::new(disk).apply(context)?;
+                Ok((boot, vec![root]))
+            }
+            Target::Partitions(boot, partitions) => Ok((boot, partitions)),
+        }
+    }
+
+    /// The storage [`Target::apply`] would produce, without touching any disk
+    pub fn preview(&self) -> Result<(BootPartition, Vec<SystemPartition>), Error> {
+        match self {
+            Target::WholeDisk(disk) => {
+                let (boot, root) = WholeDisk::new(disk).preview()?;
+                Ok((boot, vec![root]))
+            }
+            Target::Partitions(boot, partitions) => Ok((boot.clone(), partitions.clone())),
+        }
+    }
+
+    /// Prepare the storage as [`Target::apply`] does, unless the journal shows
+    /// a previous run of the same plan already did. The disk is then not
+    /// partitioned again, and its partitions are found by their PARTUUIDs.
+    pub fn resume<'c>(
+        self,
+        installer: &Installer,
+        journal: &Journal,
+        context: &'c impl Context<'c>,
+    ) -> Result<(BootPartition, Vec<SystemPartition>), Error> {
+        let Target::WholeDisk(disk) = self else {
+            return self.apply(context);
+        };
+        if let Some(prepared) = journal.prepared() {
+            match prepared.storage.target(installer) {
+                Ok(Target::Partitions(boot, partitions)) => {
+                    log::info!("Reusing the partitions of {disk} from the previous run");
+                    return Ok((boot, partitions));
+                }
+                Ok(Target::WholeDisk(_)) => {}
+                Err(e) => log::warn!("Partitions of the previous run not found, partitioning again: {e}"),
+            }
+        }
+        self.apply(context)
+    }
+}
+
+impl Manifest {
+    /// Load from a TOML file, or JSON if the extension is `.json`
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        if path.extension().is_some_and(|e| e == "json") {
+            Ok(serde_json::from_str(&contents)?)
+        } else {
+            Ok(toml::from_str(&contents)?)
+        }
+    }
+
+    /// Check everything resolves before any changes are made, returning the storage target
+    pub fn validate<'a>(
+        &self,
+        installer: &'a Installer,
+        locales: &[Locale<'_>],
+        selections: &selections::Manager,
+    ) -> Result<Target<'a>, Error> {
+        self.check()?;
+        if let Some(locale) = self.locale.as_ref() {
+            if !locales.iter().any(|l| l.name == *locale) {
+                return Err(Error::UnknownLocale(locale.clone()));
+            }
+        }
+        self.selections(selections)?;
+        let swap = self.swap(installer)?;
+
+        let target = self.target(installer)?;
+        let xbootldr = match &target {
+            Target::WholeDisk(disk) => {
+                WholeDisk::new(disk).validate()?;
+                true
+            }
+            Target::Partitions(boot, partitions) => {
+                check_partitions(partitions, swap.as_ref())?;
+                boot.xbootldr.is_some()
+            }
+        };
+        self.check_mountpoints(xbootldr)?;
+        Ok(target)
+    }
+
+    /// Checks which don't depend on the probed system
+    fn check(&self) -> Result<(), Error> {
+        if self.accounts.is_empty() {
+            return Err(Error::NoAccounts);
+        }
+        if self.encrypt && self.luks_passphrase.is_none() {
+            return Err(Error::MissingPassphrase);
+        }
+        if !self.filesystem.is_rootfs() {
+            return Err(Error::NotRootFilesystem(self.filesystem));
+        }
+        if let Some(SwapConfig::File { size_mib: 0 }) = self.swap {
+            return Err(Error::EmptySwapFile);
+        }
+        if let Some(timezone) = self.timezone.as_ref() {
+            if timezone.parse::<chrono_tz::Tz>().is_err() {
+                return Err(Error::UnknownTimezone(timezone.clone()));
+            }
+        }
+        if let Some(subvolumes) = self.btrfs_subvolumes.as_ref() {
+            check_subvolumes(subvolumes)?;
+        }
+        check_repositories(&self.repositories)?;
+        Ok(())
+    }
+
+    /// Nothing may be mounted twice, counting the subvolumes of a btrfs `/`
+    /// and the boot partitions (`/boot` only with an XBOOTLDR partition)
+    fn check_mountpoints(&self, xbootldr: bool) -> Result<(), Error> {
+        let mut mountpoints = vec![PathBuf::from("/"), PathBuf::from("/efi")];
+        if xbootldr {
+            mountpoints.push("/boot".into());
+        }
+        if let Storage::Partitions { mounts, .. } = &self.storage {
+            mountpoints.extend(mounts.iter().map(|m| PathBuf::from(&m.mountpoint)));
+        }
+        if self.filesystem == Filesystem::BTRFS {
+            let subvolumes = self.btrfs_subvolumes.clone().unwrap_or_else(Subvolume::default_layout);
+            mountpoints.extend(
+                subvolumes
+                    .into_iter()
+                    .map(|v| v.mountpoint)
+                    .filter(|m| m != Path::new("/")),
+            );
+        }
+        match duplicate_mountpoint(mountpoints.iter().map(PathBuf::as_path)) {
+            Some(mountpoint) => Err(Error::DuplicateMountpoint(mountpoint.to_path_buf())),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolve the storage section against the probed disks + partitions
+    pub fn target<'a>(&self, installer: &'a Installer) -> Result<Target<'a>, Error> {
+        self.storage.target(installer)
+    }
+
+    /// Resolve the swap configuration
+    fn swap(&self, installer: &Installer) -> Result<Option<Swap>, Error> {
+        let swap = match self.swap.as_ref() {
+            Some(SwapConfig::Partition { partition }) => {
+                Some(Swap::Partition(Box::new(find_partition(installer, partition)?)))
+            }
+            Some(SwapConfig::File { size_mib }) => Some(Swap::File {
+                size: size_mib * 1024 * 1024,
+            }),
+            Some(SwapConfig::Zram) => Some(Swap::Zram),
+            None => None,
+        };
+        Ok(swap)
+    }
+
+    /// Packages of the groups (and the mandatory ones), once the exclusions are
+    /// checked against them and the additional packages
+    /// Passphrase and UUID of the LUKS container for `/`, when encrypting
+    fn encryption(&self) -> Result<Option<(String, String)>, Error> {
+        let Some(passphrase) = self.luks_passphrase.as_ref().filter(|_| self.encrypt) else {
+            return Ok(None);
+        };
+        let uuid = match self.luks_uuid.as_ref() {
+            Some(uuid) => uuid.clone(),
+            None => Model::generate_luks_uuid()?,
+        };
+        Ok(Some((passphrase.clone(), uuid)))
+    }
+
+    fn selections(&self, selections: &selections::Manager) -> Result<BTreeSet<String>, Error> {
+        let packages = selections.selections_with(selections.with_mandatory(self.groups.iter().map(|g| g.as_str())))?;
+        let selected = packages.union(&self.packages).cloned().collect();
+        selections.check_exclusions(&selected, self.exclude.iter().map(|p| p.as_str()))?;
+        Ok(packages)
+    }
+
+    /// Build the model, once storage has been prepared via [`Target::apply`]
+    pub fn to_model<'a>(
+        &self,
+        installer: &Installer,
+        locales: &'a [Locale<'a>],
+        selections: &selections::Manager,
+        boot_partition: BootPartition,
+        partitions: Vec<SystemPartition>,
+    ) -> Result<Model<'a>, Error> {
+        let locale = match self.locale.as_ref() {
+            Some(name) => Some(
+                locales
+                    .iter()
+                    .find(|l| l.name == *name)
+                    .ok_or_else(|| Error::UnknownLocale(name.clone()))?,
+            ),
+            None => None,
+        };
+
+        let packages = self.selections(selections)?;
+        let (luks_passphrase, luks_uuid) = self.encryption()?.unzip();
+
+        Ok(Model {
+            accounts: self.accounts.iter().map(Account::from).collect(),
+            boot_partition,
+            partitions,
+            locale,
+            timezone: self.timezone.clone(),
+            groups: self.groups.clone(),
+            packages,
+            additions: self.packages.clone(),
+            exclusions: self.exclude.clone(),
+            repositories: self.repositories.clone(),
+            rootfs_type: self.filesystem,
+            chroot_only: self.chroot_only,
+            btrfs_subvolumes: self.btrfs_subvolumes.clone().unwrap_or_else(Subvolume::default_layout),
+            swap: self.swap(installer)?,
+            luks_passphrase,
+            luks_uuid,
+        })
+    }
+}
+
+/// Record a model so it can be replayed. The storage always refers to the
+/// (now existing) partitions by PARTUUID, and the chosen groups are kept along
+/// with the additions and exclusions.
+impl From<&Model<'_>> for Manifest {
+    fn from(model: &Model<'_>) -> Self {
+        let partuuid = |p: &disk::Partition| Selector::Partuuid(p.uuid.clone());
+        let root = model
+            .partitions
+            .iter()
+            .find(|p| p.mountpoint.as_deref() == Some("/"))
+            .map(|p| partuuid(&p.partition))
+            .unwrap_or_else(|| Selector::Path(PathBuf::new()));
+        let mounts = model
+            .partitions
+            .iter()
+            .filter_map(|p| match p.mountpoint.as_deref() {
+                Some("/") | None => None,
+                Some(mountpoint) => Some(Mount {
+                    partition: partuuid(&p.partition),
+                    mountpoint: mountpoint.to_owned(),
+                    filesystem: p.filesystem,
+                }),
+            })
+            .collect();
+
+        let swap = model.swap.as_ref().map(|swap| match swap {
+            Swap::Partition(partition) => SwapConfig::Partition {
+                partition: partuuid(&partition.partition),
+            },
+            Swap::File { size } => SwapConfig::File {
+                size_mib: size / 1024 / 1024,
+            },
+            Swap::Zram => SwapConfig::Zram,
+        });
+
+        Self {
+            storage: Storage::Partitions {
+                esp: partuuid(&model.boot_partition.esp),
+                format_esp: model.boot_partition.format,
+                root,
+                mounts,
+            },
+            filesystem: model.rootfs_type,
+            btrfs_subvolumes: (model.rootfs_type == Filesystem::BTRFS).then(|| model.btrfs_subvolumes.clone()),
+            encrypt: model.luks_passphrase.is_some(),
+            luks_passphrase: None,
+            luks_uuid: model.luks_uuid.clone(),
+            swap,
+            groups: model.groups.clone(),
+            packages: model.additions.clone(),
+            exclude: model.exclusions.clone(),
+            repositories: model.repositories.clone(),
+            locale: model.locale.map(|l| l.name.clone()),
+            timezone: model.timezone.clone(),
+            accounts: model.accounts.iter().map(AccountConfig::from).collect(),
+            chroot_only: model.chroot_only,
+        }
+    }
+}
+
+/// At least one repository must be enabled, and each name is only used once
+pub fn check_repositories(repositories: &[Repository]) -> Result<(), Error> {
+    if !repositories.iter().any(|r| r.enabled) {
+        return Err(Error::NoRepositories);
+    }
+    let mut names = BTreeSet::new();
+    for repository in repositories {
+        if !names.insert(&repository.name) {
+            return Err(Error::DuplicateRepository(repository.name.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Each partition may only be used once, whether mounted or as swap
+fn check_partitions(partitions: &[SystemPartition], swap: Option<&Swap>) -> Result<(), Error> {
+    let swap = match swap {
+        Some(Swap::Partition(partition)) => Some(partition.as_ref()),
+        _ => None,
+    };
+    let mut devices = BTreeSet::new();
+    for partition in partitions.iter().chain(swap) {
+        if !devices.insert(&partition.partition.path) {
+            return Err(Error::PartitionReused(partition.partition.path.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Subvolume names must be valid and unique, and mounted at absolute paths
+fn check_subvolumes(subvolumes: &[Subvolume]) -> Result<(), Error> {
+    let mut names = BTreeSet::new();
+    for subvolume in subvolumes {
+        if !subvolume.has_valid_name() || !subvolume.mountpoint.is_absolute() {
+            return Err(Error::InvalidSubvolume {
+                name: subvolume.name.clone(),
+                mountpoint: subvolume.mountpoint.clone(),
+            });
+        }
+        if !names.insert(subvolume.name.as_str()) {
+            return Err(Error::DuplicateSubvolume(subvolume.name.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Find an available system partition
+fn find_partition(installer: &Installer, selector: &Selector) -> Result<SystemPartition, Error> {
+    for candidate in installer.system_partitions() {
+        if selector.matches_partition(&candidate.partition)? {
+            return Ok(candidate.clone());
+        }
+    }
+    Err(Error::NoSuchPartition(selector.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, path::Path};
+
+    use system::disk::Partition;
+
+    use super::{
+        check_partitions, check_repositories, check_subvolumes, Error, Manifest, Mount, Selector, Storage, SwapConfig,
+    };
+    use crate::{Account, BootPartition, Filesystem, Model, Repository, Subvolume, Swap, SystemPartition};
+
+    #[test]
+    fn test_parse_toml() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            filesystem = "btrfs"
+            groups = ["develop", "gnome"]
+            locale = "en_US.UTF-8"
+
+            [storage]
+            strategy = "partitions"
+            esp = { partuuid = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b" }
+            root = { path = "/dev/sda3" }
+            mounts = [{ partition = { path = "/dev/sda4" }, mountpoint = "/home", filesystem = "ext4" }]
+
+            [swap]
+            kind = "file"
+            size-mib = 4096
+
+            [[accounts]]
+            username = "root"
+            password-hash = "$6$salt$hash"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.filesystem, Filesystem::BTRFS);
+        assert!(matches!(manifest.swap, Some(SwapConfig::File { size_mib: 4096 })));
+        let Storage::Partitions { esp, mounts, .. } = manifest.storage else {
+            panic!("expected partitions");
+        };
+        assert!(matches!(esp, Selector::Partuuid(_)));
+        assert_eq!(mounts[0].filesystem, Some(Filesystem::EXT4));
+        assert_eq!(manifest.accounts[0].password_hash.as_deref(), Some("$6$salt$hash"));
+        assert_eq!(manifest.repositories, Repository::defaults());
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let manifest: Manifest = serde_json::from_str(
+            r#"{
+                "filesystem": "xfs",
+                "groups": ["develop"],
+                "storage": { "strategy": "whole-disk", "disk": { "serial": "ABC123" } },
+                "accounts": [{ "username": "root", "password": "hunter2" }]
+            }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            manifest.storage,
+            Storage::WholeDisk {
+                disk: Selector::Serial(_)
+            }
+        ));
+    }
+
+    #[test]
+    fn test_unknown_filesystem() {
+        let res = toml::from_str::<Manifest>(
+            r#"
+            filesystem = "zfs"
+            groups = []
+            [storage]
+            strategy = "whole-disk"
+            disk = { path = "/dev/sda" }
+            "#,
+        );
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_not_root_filesystem() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            filesystem = "vfat"
+            [storage]
+            strategy = "whole-disk"
+            disk = { path = "/dev/sda" }
+            [[accounts]]
+            username = "root"
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(
+            manifest.check(),
+            Err(Error::NotRootFilesystem(Filesystem::VFAT))
+        ));
+        let manifest = Manifest {
+            filesystem: Filesystem::EXT4,
+            ..manifest
+        };
+        assert!(manifest.check().is_ok());
+    }
+
+    #[test]
+    fn test_unknown_timezone() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            filesystem = "ext4"
+            timezone = "Europe/London"
+            [storage]
+            strategy = "whole-disk"
+            disk = { path = "/dev/sda" }
+            [[accounts]]
+            username = "root"
+            "#,
+        )
+        .unwrap();
+        assert!(manifest.check().is_ok());
+        let manifest = Manifest {
+            timezone: Some("Europe/Atlantis".into()),
+            ..manifest
+        };
+        assert!(matches!(manifest.check(), Err(Error::UnknownTimezone(_))));
+    }
+
+    #[test]
+    fn test_encryption() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            filesystem = "ext4"
+            luks-passphrase = "secret"
+            luks-uuid = "4c5b2f0e-4d4a-4b1e-9d55-2f3e0b7c9a61"
+            [storage]
+            strategy = "whole-disk"
+            disk = { path = "/dev/sda" }
+            [[accounts]]
+            username = "root"
+            "#,
+        )
+        .unwrap();
+        assert!(!manifest.encrypt);
+        assert!(manifest.encryption().unwrap().is_none());
+
+        let manifest = Manifest {
+            encrypt: true,
+            ..manifest
+        };
+        assert_eq!(
+            manifest.encryption().unwrap(),
+            Some(("secret".into(), "4c5b2f0e-4d4a-4b1e-9d55-2f3e0b7c9a61".into()))
+        );
+    }
+
+    #[test]
+    fn test_partition_reused() {
+        let system = |path: &str, mountpoint: Option<&str>| SystemPartition {
+            partition: Partition {
+                path: path.into(),
+                ..Default::default()
+            },
+            mountpoint: mountpoint.map(String::from),
+            parent_desc: String::new(),
+            filesystem: None,
+        };
+        let partitions = [system("/dev/sda2", Some("/")), system("/dev/sda3", Some("/home"))];
+        assert!(check_partitions(&partitions, None).is_ok());
+        assert!(check_partitions(&partitions, Some(&Swap::Zram)).is_ok());
+        assert!(matches!(
+            check_partitions(&partitions, Some(&Swap::Partition(Box::new(system("/dev/sda3", None))))),
+            Err(Error::PartitionReused(path)) if path == Path::new("/dev/sda3")
+        ));
+        assert!(matches!(
+            check_partitions(
+                &[system("/dev/sda2", Some("/")), system("/dev/sda2", Some("/srv"))],
+                None
+            ),
+            Err(Error::PartitionReused(_))
+        ));
+    }
+
+    #[test]
+    fn test_empty_swap_file() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            filesystem = "ext4"
+            [storage]
+            strategy = "whole-disk"
+            disk = { path = "/dev/sda" }
+            [swap]
+            kind = "file"
+            size-mib = 0
+            [[accounts]]
+            username = "root"
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(manifest.check(), Err(Error::EmptySwapFile)));
+    }
+
+    #[test]
+    fn test_duplicate_mountpoint() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            filesystem = "btrfs"
+            [storage]
+            strategy = "partitions"
+            esp = { path = "/dev/sda1" }
+            root = { path = "/dev/sda2" }
+            mounts = [{ partition = { path = "/dev/sda3" }, mountpoint = "/home/" }]
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(
+            manifest.check_mountpoints(false),
+            Err(Error::DuplicateMountpoint(m)) if m == Path::new("/home")
+        ));
+
+        let manifest = Manifest {
+            filesystem: Filesystem::EXT4,
+            ..manifest
+        };
+        assert!(manifest.check_mountpoints(false).is_ok());
+        let manifest = Manifest {
+            storage: Storage::Partitions {
+                esp: Selector::Path("/dev/sda1".into()),
+                format_esp: false,
+                root: Selector::Path("/dev/sda2".into()),
+                mounts: vec![Mount {
+                    partition: Selector::Path("/dev/sda3".into()),
+                    mountpoint: "/boot".into(),
+                    filesystem: None,
+                }],
+            },
+            ..manifest
+        };
+        assert!(manifest.check_mountpoints(false).is_ok());
+        assert!(matches!(
+            manifest.check_mountpoints(true),
+            Err(Error::DuplicateMountpoint(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_subvolumes() {
+        assert!(check_subvolumes(&Subvolume::default_layout()).is_ok());
+        for (name, mountpoint) in [("", "/srv"), ("/", "/srv"), ("@srv/data", "/srv"), ("@srv", "srv")] {
+            assert!(matches!(
+                check_subvolumes(&[Subvolume::new(name, mountpoint)]),
+                Err(Error::InvalidSubvolume { .. })
+            ));
+        }
+        assert!(matches!(
+            check_subvolumes(&[Subvolume::new("@", "/"), Subvolume::new("@", "/home")]),
+            Err(Error::DuplicateSubvolume(name)) if name == "@"
+        ));
+    }
+
+    #[test]
+    fn test_check_repositories() {
+        assert!(check_repositories(&Repository::defaults()).is_ok());
+        assert!(matches!(check_repositories(&[]), Err(Error::NoRepositories)));
+
+        let disabled = Repository {
+            enabled: false,
+            ..Repository::official()
+        };
+        assert!(matches!(check_repositories(&[disabled]), Err(Error::NoRepositories)));
+
+        let local = Repository::new("unstable", "file:///srv/repo/stone.index");
+        assert!(matches!(
+            check_repositories(&[Repository::official(), local]),
+            Err(Error::DuplicateRepository(name)) if name == "unstable"
+        ));
+    }
+
+    #[test]
+    fn test_from_model() {
+        let partition = |uuid: &str| Partition {
+            uuid: uuid.into(),
+            ..Default::default()
+        };
+        let system = |uuid: &str, mountpoint: &str| SystemPartition {
+            partition: partition(uuid),
+            mountpoint: Some(mountpoint.into()),
+            parent_desc: String::new(),
+            filesystem: None,
+        };
+        let model = Model {
+            accounts: BTreeSet::from([
+                Account::root().with_hashed_password("$6$salt$hash"),
+                Account::new("user").with_password("hunter2"),
+            ]),
+            boot_partition: BootPartition {
+                esp: partition("esp"),
+                xbootldr: None,
+                parent_desc: String::new(),
+                format: true,
+            },
+            partitions: vec![system("root", "/"), system("home", "/home")],
+            locale: None,
+            timezone: Some("Europe/London".into()),
+            groups: vec!["develop".into()],
+            packages: BTreeSet::from(["moss".into(), "nano".into()]),
+            additions: BTreeSet::from(["vim".into()]),
+            exclusions: BTreeSet::from(["nano".into()]),
+            repositories: vec![Repository::new("local", "file:///srv/repo/stone.index").with_priority(10)],
+            rootfs_type: Filesystem::XFS,
+            btrfs_subvolumes: vec![],
+            swap: Some(Swap::File {
+                size: 4 * 1024 * 1024 * 1024,
+            }),
+            luks_passphrase: Some("secret".into()),
+            luks_uuid: Some("4c5b2f0e-4d4a-4b1e-9d55-2f3e0b7c9a61".into()),
+            chroot_only: false,
+        };
+
+        let manifest = Manifest::from(&model);
+        assert!(manifest.encrypt);
+        assert!(manifest.luks_passphrase.is_none());
+        assert!(manifest.accounts.iter().all(|a| a.password.is_none()));
+        assert_eq!(manifest.accounts[0].password_hash.as_deref(), Some("$6$salt$hash"));
+
+        // Must survive a round trip through either format
+        let json = serde_json::to_string(&manifest).unwrap();
+        let replayed: Manifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(replayed.groups, model.groups);
+        assert_eq!(replayed.packages, model.additions);
+        assert_eq!(replayed.exclude, model.exclusions);
+        assert_eq!(replayed.repositories, model.repositories);
+        assert_eq!(replayed.luks_uuid, model.luks_uuid);
+        let replayed: Manifest = toml::from_str(&toml::to_string(&manifest).unwrap()).unwrap();
+        assert!(matches!(replayed.swap, Some(SwapConfig::File { size_mib: 4096 })));
+        let Storage::Partitions { root, mounts, .. } = replayed.storage else {
+            panic!("expected partitions");
+        };
+        assert!(matches!(root, Selector::Partuuid(uuid) if uuid == "root"));
+        assert_eq!(mounts[0].mountpoint, "/home");
+    }
+}
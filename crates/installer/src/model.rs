@@ -2,11 +2,12 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, path::Path};
 
+use fs_err as fs;
 use system::locale::Locale;
 
-use crate::{Account, BootPartition, SystemPartition};
+use crate::{selections, Account, BootPartition, Filesystem, Repository, Subvolume, Swap, SystemPartition};
 
 /// Core model for the installation target
 #[derive(Debug)]
@@ -26,9 +27,80 @@ pub struct Model<'a> {
     /// Timezone ID
     pub timezone: Option<String>,
 
-    /// Package selections
+    /// Selection groups chosen, besides the mandatory ones
+    pub groups: Vec<String>,
+
+    /// Package selections, resolved from the groups
     pub packages: BTreeSet<String>,
 
+    /// Packages installed in addition to the selections
+    pub additions: BTreeSet<String>,
+
+    /// Packages removed from the selections
+    pub exclusions: BTreeSet<String>,
+
+    /// Repositories to install from, see [`Repository::defaults`]
+    pub repositories: Vec<Repository>,
+
     /// rootfs format
-    pub rootfs_type: String,
+    pub rootfs_type: Filesystem,
+
+    /// Subvolume layout when `rootfs_type` is btrfs
+    pub btrfs_subvolumes: Vec<Subvolume>,
+
+    /// Swap partition, swapfile or zram
+    pub swap: Option<Swap>,
+
+    /// Encrypt the root partition with LUKS2 using this passphrase
+    pub luks_passphrase: Option<String>,
+
+    /// UUID of the LUKS container, fixed up front so the compiled steps are reproducible
+    pub luks_uuid: Option<String>,
+
+    /// Only populate the target, leaving the host firmware's EFI variables alone
+    pub chroot_only: bool,
+}
+
+impl Model<'_> {
+    /// Generate a UUID for a new LUKS container
+    pub fn generate_luks_uuid() -> Result<String, std::io::Error> {
+        Ok(fs::read_to_string("/proc/sys/kernel/random/uuid")?.trim().to_owned())
+    }
+
+    /// Everything mounted in the target: partitions, btrfs subvolumes and the boot partitions
+    pub fn mountpoints(&self) -> Vec<&Path> {
+        let mut mountpoints = self
+            .partitions
+            .iter()
+            .filter_map(|p| p.mountpoint.as_deref().map(Path::new))
+            .collect::<Vec<_>>();
+        if self.rootfs_type == Filesystem::BTRFS {
+            mountpoints.extend(
+                self.btrfs_subvolumes
+                    .iter()
+                    .map(|v| v.mountpoint.as_path())
+                    .filter(|m| *m != Path::new("/")),
+            );
+        }
+        mountpoints.push(Path::new("/efi"));
+        if self.boot_partition.xbootldr.is_some() {
+            mountpoints.push(Path::new("/boot"));
+        }
+        mountpoints
+    }
+
+    /// The selections with the additions, less the exclusions
+    pub fn resolved_packages(&self) -> BTreeSet<String> {
+        self.packages
+            .union(&self.additions)
+            .filter(|p| !self.exclusions.iter().any(|e| selections::excludes(e, p)))
+            .cloned()
+            .collect()
+    }
+}
+
+/// The first mountpoint given more than once
+pub(crate) fn duplicate_mountpoint<'a>(mountpoints: impl IntoIterator<Item = &'a Path>) -> Option<&'a Path> {
+    let mut seen = BTreeSet::new();
+    mountpoints.into_iter().find(|m| !seen.insert(*m))
 }
@@ -0,0 +1,144 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Offline installation from a local repository
+//!
+//! A live medium may bundle a moss repository (a `stone.index` alongside its
+//! packages), or simply a directory of `.stone` packages which is indexed on
+//! the host before use. Installing from it as the only [`Repository`] needs no
+//! network access, provided every package is available: check that with
+//! [`Source::verify`] before touching any disk.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    index::{self, Index},
+    Repository,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("index: {0}")]
+    Index(#[from] index::Error),
+
+    #[error("{0} is not a stone index or directory of .stone packages")]
+    NotARepository(PathBuf),
+
+    #[error("no bundled repository found")]
+    NotFound,
+
+    #[error("not available offline: {}", .0.iter().cloned().collect::<Vec<_>>().join(", "))]
+    Unavailable(BTreeSet<String>),
+}
+
+/// Where a live medium may bundle a repository
+pub const BUNDLED_PATHS: &[&str] = &["/run/initramfs/live/repo", "/usr/share/lichen/repo"];
+
+/// Name of the local repository in the target
+pub const REPOSITORY_NAME: &str = "local";
+
+/// Where a directory of `.stone` packages is indexed, on the host
+pub const INDEX_DIR: &str = "/run/lichen/repo";
+
+/// A local repository
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// An existing `stone.index`
+    Index(PathBuf),
+
+    /// A directory of `.stone` packages without an index
+    Stones(PathBuf),
+}
+
+impl Source {
+    /// Find the repository bundled on the live medium
+    pub fn detect() -> Result<Self, Error> {
+        BUNDLED_PATHS
+            .iter()
+            .find_map(|path| Self::from_path(path).ok())
+            .ok_or(Error::NotFound)
+    }
+
+    /// Use a `stone.index`, or a directory containing one or `.stone` packages
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        if path.is_file() {
+            return Ok(Self::Index(path.to_path_buf()));
+        }
+        let index = path.join("stone.index");
+        if index.is_file() {
+            return Ok(Self::Index(index));
+        }
+        if path.is_dir() && !index::stones_in(path)?.is_empty() {
+            return Ok(Self::Stones(path.to_path_buf()));
+        }
+        Err(Error::NotARepository(path.to_path_buf()))
+    }
+
+    /// Read the packages the repository offers
+    pub fn index(&self) -> Result<Index, Error> {
+        Ok(match self {
+            Source::Index(path) => Index::from_path(path)?,
+            Source::Stones(dir) => Index::from_stones(dir)?,
+        })
+    }
+
+    /// Ensure every package (or provider) can be installed from this repository
+    pub fn verify<'a, I: IntoIterator<Item = &'a str>>(&self, packages: I) -> Result<(), Error> {
+        let missing = self.index()?.missing(packages);
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Unavailable(missing.into_iter().map(String::from).collect()))
+        }
+    }
+
+    /// The repository to install from
+    pub fn repository(&self) -> Repository {
+        match self {
+            Source::Index(path) => Repository::new(REPOSITORY_NAME, format!("file://{}", path.display())),
+            Source::Stones(dir) => Repository {
+                stones: Some(dir.clone()),
+                ..Repository::new(REPOSITORY_NAME, format!("file://{INDEX_DIR}/stone.index"))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fs_err as fs;
+
+    use super::{Error, Source};
+    use crate::index::tests::stone;
+
+    #[test]
+    fn test_stones_dir() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        assert!(matches!(Source::from_path(&dir), Err(Error::NotARepository(_))));
+
+        fs::write(dir.join("bash.stone"), stone(&[("bash", &[(6, "bash")])])).unwrap();
+        let source = Source::from_path(&dir).unwrap();
+        assert_eq!(source, Source::Stones(dir.clone()));
+        assert_eq!(source.repository().stones, Some(dir.clone()));
+        assert!(source.verify(["binary(bash)"]).is_ok());
+        assert!(matches!(
+            source.verify(["bash", "binary(zsh)"]),
+            Err(Error::Unavailable(missing)) if missing.len() == 1
+        ));
+
+        fs::write(dir.join("stone.index"), stone(&[])).unwrap();
+        assert_eq!(Source::from_path(&dir).unwrap(), Source::Index(dir.join("stone.index")));
+        assert_eq!(
+            Source::from_path(&dir).unwrap().repository().uri,
+            format!("file://{}", dir.join("stone.index").display())
+        );
+    }
+}
@@ -12,6 +12,8 @@ use std::fmt::Display;
 use human_bytes::human_bytes;
 use system::disk;
 
+use crate::Filesystem;
+
 /// A boot partition is an EFI System Partition which may or may
 /// not be paired with an `XBOOTLDR` partition, relative to its location
 /// on the same GPT disk.
@@ -21,6 +23,17 @@ pub struct BootPartition {
     pub(crate) esp: disk::Partition,
     pub(crate) xbootldr: Option<disk::Partition>,
     pub(crate) parent_desc: String,
+
+    /// Whether the ESP (and XBOOTLDR) should be freshly formatted as FAT32
+    pub format: bool,
+}
+
+impl BootPartition {
+    /// Whether the ESP (and XBOOTLDR, if present) already carry a FAT filesystem
+    pub fn is_formatted(&self) -> bool {
+        let is_fat = |p: &disk::Partition| matches!(p.sb, Some(disk::SuperblockKind::FAT));
+        is_fat(&self.esp) && self.xbootldr.as_ref().is_none_or(is_fat)
+    }
 }
 
 impl Display for BootPartition {
@@ -54,6 +67,10 @@ pub struct SystemPartition {
     pub mountpoint: Option<String>,
 
     pub(crate) parent_desc: String,
+
+    /// Format with this filesystem, or keep the existing one when `None`.
+    /// `/` is always formatted using [`crate::Model::rootfs_type`].
+    pub filesystem: Option<Filesystem>,
 }
 
 impl Display for SystemPartition {
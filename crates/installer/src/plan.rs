@@ -0,0 +1,417 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Dry-run installation plans
+//!
+//! Executing the compiled steps against a [`DryRun`] context records every
+//! command and file operation instead of performing it, producing a [`Plan`]
+//! that can be reviewed (or diffed) before anything destructive happens.
+
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Output},
+};
+
+use serde::Serialize;
+use system::disk::{self, Disk, Layout, Table};
+
+use crate::{
+    steps::{self, Cleanup, Context, Step},
+    strategy::WholeDisk,
+};
+
+/// A single operation a step would perform
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Operation {
+    /// Run a command. Input is never recorded as it may carry secrets.
+    Command {
+        program: String,
+        args: Vec<String>,
+        stdin: bool,
+    },
+    CreateDir {
+        path: PathBuf,
+    },
+    WriteFile {
+        path: PathBuf,
+        contents: String,
+    },
+    /// Written readable by root alone
+    WritePrivateFile {
+        path: PathBuf,
+        contents: String,
+    },
+    CopyFile {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    RemoveFile {
+        path: PathBuf,
+    },
+    Symlink {
+        target: PathBuf,
+        link: PathBuf,
+    },
+    /// Replace the partition table of a disk, listing the new partitions
+    WritePartitionTable {
+        disk: PathBuf,
+        partitions: Vec<String>,
+    },
+}
+
+impl Operation {
+    fn command(cmd: &Command, input: Option<&str>) -> Self {
+        Self::Command {
+            program: cmd.get_program().to_string_lossy().into(),
+            args: cmd.get_args().map(|a| a.to_string_lossy().into()).collect(),
+            stdin: input.is_some(),
+        }
+    }
+}
+
+/// Stand-in for a UUID only generated during installation, such as a PARTUUID,
+/// so plans of the same installation are identical
+pub fn placeholder_uuid(index: u32) -> String {
+    format!("00000000-0000-4000-8000-{index:012x}")
+}
+
+/// Quote an argument for display as a shell command
+fn quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,@+%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.into()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operation::Command { program, args, stdin } => {
+                let line = std::iter::once(program)
+                    .chain(args)
+                    .map(|a| quote(a))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let stdin = if *stdin { " < (stdin)" } else { "" };
+                write!(f, "$ {line}{stdin}")
+            }
+            Operation::CreateDir { path } => write!(f, "mkdir -p {}", path.display()),
+            Operation::WriteFile { path, contents } | Operation::WritePrivateFile { path, contents } => {
+                let mode = match self {
+                    Operation::WritePrivateFile { .. } => " (0600)",
+                    _ => "",
+                };
+                write!(f, "write{mode} {}", path.display())?;
+                for line in contents.lines() {
+                    write!(f, "\n  | {line}")?;
+                }
+                Ok(())
+            }
+            Operation::CopyFile { from, to } => write!(f, "copy {} -> {}", from.display(), to.display()),
+            Operation::RemoveFile { path } => write!(f, "rm -f {}", path.display()),
+            Operation::Symlink { target, link } => write!(f, "ln -s {} {}", target.display(), link.display()),
+            Operation::WritePartitionTable { disk, partitions } => {
+                write!(f, "write partition table {}", disk.display())?;
+                for partition in partitions {
+                    write!(f, "\n  | {partition}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A context which records operations instead of performing them
+#[derive(Debug)]
+pub struct DryRun {
+    root: PathBuf,
+    operations: RefCell<Vec<Operation>>,
+}
+
+impl DryRun {
+    /// Create a new dry-run context for the given target root
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            operations: RefCell::new(vec![]),
+        }
+    }
+
+    /// Take all operations recorded so far
+    pub fn take(&self) -> Vec<Operation> {
+        self.operations.take()
+    }
+
+    fn record(&self, operation: Operation) -> Result<(), steps::Error> {
+        self.operations.borrow_mut().push(operation);
+        Ok(())
+    }
+}
+
+impl<'a> Context<'a> for DryRun {
+    fn root(&'a self) -> &'a PathBuf {
+        &self.root
+    }
+
+    fn run_command(&self, cmd: &mut Command) -> Result<(), steps::Error> {
+        self.record(Operation::command(cmd, None))
+    }
+
+    fn run_command_captured(&self, cmd: &mut Command, input: Option<&str>) -> Result<Output, steps::Error> {
+        self.record(Operation::command(cmd, input))?;
+        Ok(Output {
+            status: ExitStatus::from_raw(0),
+            stdout: vec![],
+            stderr: vec![],
+        })
+    }
+
+    fn run_command_streamed(&self, cmd: &mut Command, _on_line: &mut dyn FnMut(&str)) -> Result<(), steps::Error> {
+        self.record(Operation::command(cmd, None))
+    }
+
+    fn write_partition_table(
+        &self,
+        disk: &Disk,
+        build: &dyn Fn(&mut Table) -> Result<(), disk::Error>,
+    ) -> Result<Layout, steps::Error> {
+        let mut table = disk.new_table(true)?;
+        build(&mut table)?;
+        let layout = table.commit()?;
+        self.record(Operation::WritePartitionTable {
+            disk: disk.path.clone(),
+            partitions: layout.entries.iter().map(|e| e.to_string()).collect(),
+        })?;
+        Ok(layout)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), steps::Error> {
+        self.record(Operation::CreateDir { path: path.into() })
+    }
+
+    fn write_file(&self, path: &Path, contents: &str) -> Result<(), steps::Error> {
+        self.record(Operation::WriteFile {
+            path: path.into(),
+            contents: contents.into(),
+        })
+    }
+
+    fn write_private_file(&self, path: &Path, contents: &str) -> Result<(), steps::Error> {
+        self.record(Operation::WritePrivateFile {
+            path: path.into(),
+            contents: contents.into(),
+        })
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path) -> Result<(), steps::Error> {
+        self.record(Operation::CopyFile {
+            from: from.into(),
+            to: to.into(),
+        })
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), steps::Error> {
+        self.record(Operation::RemoveFile { path: path.into() })
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> Result<(), steps::Error> {
+        self.record(Operation::Symlink {
+            target: target.into(),
+            link: link.into(),
+        })
+    }
+}
+
+/// A step (or cleanup) and everything it would do
+#[derive(Debug, Serialize)]
+pub struct PlannedStep {
+    pub name: &'static str,
+    pub title: String,
+    pub description: String,
+    pub operations: Vec<Operation>,
+
+    /// Set when the remaining operations depend on the installed system,
+    /// i.e. the bootloader entries need the installed kernels.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unresolved: Option<String>,
+}
+
+impl Display for PlannedStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.title, self.name, self.description)?;
+        for operation in self.operations.iter() {
+            for line in operation.to_string().lines() {
+                write!(f, "\n    {line}")?;
+            }
+        }
+        if let Some(reason) = self.unresolved.as_ref() {
+            write!(f, "\n    ... determined during installation: {reason}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Complete plan of an installation
+#[derive(Debug, Serialize)]
+pub struct Plan {
+    /// Wiping and partitioning the disk of a whole-disk installation, which
+    /// happens before any of the steps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partitioning: Option<PlannedStep>,
+    pub steps: Vec<PlannedStep>,
+    pub cleanups: Vec<PlannedStep>,
+}
+
+impl Plan {
+    /// Build the plan by executing all steps and cleanups against the dry-run context
+    pub fn new<'a>(steps: &[Step<'a>], cleanups: &[Cleanup], context: &'a DryRun) -> Self {
+        let planned = |name, title, description, res: Result<(), steps::Error>| PlannedStep {
+            name,
+            title,
+            description,
+            operations: context.take(),
+            unresolved: res.err().map(|e| e.to_string()),
+        };
+        Self {
+            partitioning: None,
+            steps: steps
+                .iter()
+                .map(|s| planned(s.name(), s.title(), s.describe(), s.execute(context)))
+                .collect(),
+            cleanups: cleanups
+                .iter()
+                .map(|c| planned(c.name(), c.title(), c.describe(), c.execute(context)))
+                .collect(),
+        }
+    }
+
+    /// Include partitioning the whole disk with the given strategy
+    pub fn with_partitioning(self, strategy: &WholeDisk<'_>, context: &DryRun) -> Self {
+        let res = strategy.prepare(context);
+        Self {
+            partitioning: Some(PlannedStep {
+                name: "partition",
+                title: "Partition disk".into(),
+                description: strategy.describe(),
+                operations: context.take(),
+                unresolved: res.err().map(|e| e.to_string()),
+            }),
+            ..self
+        }
+    }
+
+    /// Machine readable form of the plan
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(partitioning) = self.partitioning.as_ref() {
+            writeln!(f, "Partitioning:\n  -  {partitioning}\n\nSteps:")?;
+        }
+        for (index, step) in self.steps.iter().enumerate() {
+            writeln!(f, "{:>3}. {step}", index + 1)?;
+        }
+        writeln!(f, "\nCleanups:")?;
+        for (index, cleanup) in self.cleanups.iter().enumerate() {
+            writeln!(f, "{:>3}. {cleanup}", index + 1)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, path::Path, process::Command};
+
+    use fs_err as fs;
+    use system::disk::{Disk, DiskKind};
+
+    use crate::{steps::Context, strategy::WholeDisk};
+
+    use super::{DryRun, Operation, Plan};
+
+    #[test]
+    fn test_records_operations() {
+        let context = DryRun::new("/tmp/lichen");
+        let mut cmd = Command::new("chroot");
+        cmd.args(["/tmp/lichen", "chpasswd"]);
+        context.run_command_captured(&mut cmd, Some("root:hunter2\n")).unwrap();
+        context
+            .write_file(Path::new("/tmp/lichen/etc/fstab"), "# fstab")
+            .unwrap();
+
+        let operations = context.take();
+        assert_eq!(operations.len(), 2);
+        assert_eq!(operations[0].to_string(), "$ chroot /tmp/lichen chpasswd < (stdin)");
+        assert!(matches!(&operations[1], Operation::WriteFile { contents, .. } if contents == "# fstab"));
+        assert!(context.take().is_empty());
+    }
+
+    #[test]
+    fn test_whole_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("disk.img");
+        let size = 32 * 1024 * 1024 * 1024;
+        fs::File::create(&path).unwrap().set_len(size).unwrap();
+        let disk = Disk {
+            path: path.clone(),
+            kind: DiskKind::SSD,
+            model: Some("Image".into()),
+            vendor: None,
+            serial: None,
+            block_size: 512,
+            size: size / 512,
+        };
+
+        let context = DryRun::new("/tmp/lichen");
+        let plan = Plan::new(&[], &[], &context).with_partitioning(&WholeDisk::new(&disk), &context);
+        let partitioning = plan.partitioning.as_ref().unwrap();
+        assert!(partitioning.unresolved.is_none());
+        let operations = partitioning
+            .operations
+            .iter()
+            .map(|o| o.to_string())
+            .collect::<Vec<_>>();
+        let path = path.display();
+        assert_eq!(operations[0], format!("$ wipefs --all {path}"));
+        assert!(operations[1].starts_with(&format!("write partition table {path}")));
+        assert!(operations[1].contains("EFI System Partition"));
+        assert_eq!(operations[2], "$ udevadm settle");
+        assert_eq!(operations[3..], [1, 2, 3].map(|n| format!("$ wipefs --all {path}{n}")));
+
+        assert!(plan.to_string().starts_with("Partitioning:"));
+        assert!(plan.to_json().unwrap().contains(r#""kind": "write-partition-table""#));
+
+        // Nothing was written, not even the protective MBR + GPT header
+        let mut header = [0; 1024];
+        fs::File::open(dir.path().join("disk.img"))
+            .unwrap()
+            .read_exact(&mut header)
+            .unwrap();
+        assert!(header.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_placeholder_uuid() {
+        assert_eq!(super::placeholder_uuid(2), "00000000-0000-4000-8000-000000000002");
+        assert_eq!(super::placeholder_uuid(2).len(), 36);
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(super::quote("-o"), "-o");
+        assert_eq!(super::quote("rw,subvol=@"), "rw,subvol=@");
+        assert_eq!(super::quote("Jane Doe"), "'Jane Doe'");
+        assert_eq!(super::quote(""), "''");
+    }
+}
@@ -0,0 +1,110 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! moss repositories configured in the target
+
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// A moss repository, added to the target before any packages are installed
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Repository {
+    /// Repository name, i.e. `unstable`
+    pub name: String,
+
+    /// URI of the stone index, `https://` or `file://`
+    pub uri: String,
+
+    /// Higher priority repositories win when a package is in several
+    #[serde(default)]
+    pub priority: u64,
+
+    /// Disabled repositories are not added to the target
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+
+    /// Directory of `.stone` packages, indexed into the (`file://`) `uri` before use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stones: Option<PathBuf>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Repository {
+    /// Index of the official repository
+    pub const OFFICIAL_URI: &'static str = "https://dev.serpentos.com/volatile/x86_64/stone.index";
+
+    /// Create a new, enabled repository with priority 0
+    pub fn new(name: impl Into<String>, uri: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            uri: uri.into(),
+            priority: 0,
+            enabled: true,
+            stones: None,
+        }
+    }
+
+    /// Set the priority
+    pub fn with_priority(self, priority: u64) -> Self {
+        Self { priority, ..self }
+    }
+
+    /// The official repository
+    pub fn official() -> Self {
+        Self::new("unstable", Self::OFFICIAL_URI)
+    }
+
+    /// Repositories used unless configured otherwise
+    pub fn defaults() -> Vec<Self> {
+        vec![Self::official()]
+    }
+}
+
+impl Display for Repository {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}, priority {})", self.name, self.uri, self.priority)
+    }
+}
+
+/// Parse `name=uri` or `name=uri@priority`, as given on the command line
+impl FromStr for Repository {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, uri) = s
+            .split_once('=')
+            .filter(|(name, uri)| !name.is_empty() && !uri.is_empty())
+            .ok_or_else(|| format!("expected `name=uri[@priority]`, got `{s}`"))?;
+        match uri.rsplit_once('@') {
+            Some((uri, priority)) if priority.chars().all(|c| c.is_ascii_digit()) && !priority.is_empty() => {
+                let priority = priority.parse().map_err(|e| format!("invalid priority: {e}"))?;
+                Ok(Self::new(name, uri).with_priority(priority))
+            }
+            _ => Ok(Self::new(name, uri)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Repository;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(
+            "mirror=https://mirror.example.com/stone.index".parse::<Repository>(),
+            Ok(Repository::new("mirror", "https://mirror.example.com/stone.index"))
+        );
+        assert_eq!(
+            "local=file:///srv/repo/stone.index@10".parse::<Repository>(),
+            Ok(Repository::new("local", "file:///srv/repo/stone.index").with_priority(10))
+        );
+        assert!("https://mirror.example.com".parse::<Repository>().is_err());
+    }
+}
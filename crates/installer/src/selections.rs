@@ -3,24 +3,88 @@
 // SPDX-License-Identifier: MPL-2.0
 
 //! Deserialising of selections from JSON files
+//!
+//! Groups are loaded at runtime from [`SYSTEM_DIR`], where a group in
+//! [`OVERRIDE_DIR`] replaces the system group of the same name. Without a
+//! [`SYSTEM_DIR`], the groups built into lichen are used instead.
+//!
+//! Groups declaring `hardware` conditions are suggested by [`Manager::detect`]
+//! when the probed [`Hardware`] matches any of them.
 
 use std::{
     collections::{BTreeMap, BTreeSet},
     fmt::Display,
+    path::{Path, PathBuf},
     str::FromStr,
 };
 
+use fs_err as fs;
 use serde::Deserialize;
+use system::hardware::{self, Hardware};
 use thiserror::Error;
 
+use crate::index::Index;
+
 /// Selection handling errors
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("serde: {0}")]
     Deserialize(#[from] serde_json::Error),
 
-    #[error("unknown group")]
+    #[error("io: {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("{0}: {1}")]
+    InvalidGroup(PathBuf, serde_json::Error),
+
+    #[error("no selection groups found in {} or {}", .0.display(), .1.display())]
+    NoGroups(PathBuf, PathBuf),
+
+    #[error("group {name} is defined by both {} and {}", .first.display(), .second.display())]
+    DuplicateGroup {
+        name: String,
+        first: PathBuf,
+        second: PathBuf,
+    },
+
+    #[error("group {group} depends on unknown group {depends}")]
+    UnknownDependency { group: String, depends: String },
+
+    #[error("unknown group: {0}")]
     UnknownGroup(String),
+
+    #[error("dependency cycle: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+
+    #[error("group {0} conflicts with {1}")]
+    Conflict(String, String),
+
+    #[error("group {group} is not available for {architecture}")]
+    UnsupportedArchitecture { group: String, architecture: String },
+
+    #[error("{package} cannot be excluded, it is required by the mandatory group {group}")]
+    RequiredPackage { package: String, group: String },
+
+    #[error("{0} cannot be excluded, nothing selected matches it")]
+    UnmatchedExclusion(String),
+
+    #[error("packages not available: {}", describe_unavailable(.0))]
+    Unavailable(BTreeMap<String, BTreeSet<String>>),
+}
+
+/// Where the distribution installs selection groups
+pub const SYSTEM_DIR: &str = "/usr/share/lichen/selections";
+
+/// Where local groups are added, or system groups replaced
+pub const OVERRIDE_DIR: &str = "/etc/lichen/selections";
+
+/// Render missing packages as `group: a, b; other: c`
+fn describe_unavailable(unavailable: &BTreeMap<String, BTreeSet<String>>) -> String {
+    unavailable
+        .iter()
+        .map(|(group, names)| format!("{group}: {}", names.iter().cloned().collect::<Vec<_>>().join(", ")))
+        .collect::<Vec<_>>()
+        .join("; ")
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -40,6 +104,96 @@ pub struct Group {
 
     /// A set of package names (moss-encoded) that form this selection
     pub required: Vec<String>,
+
+    /// Packages installed along with the group, unless declined
+    #[serde(default)]
+    pub recommended: Vec<String>,
+
+    /// Packages offered with the group, but not installed by default
+    #[serde(default)]
+    pub optional: Vec<String>,
+
+    /// Groups which cannot be installed alongside this one
+    #[serde(default)]
+    pub conflicts: Vec<String>,
+
+    /// Architectures the group is available for, all when empty
+    #[serde(default)]
+    pub architectures: Vec<String>,
+
+    /// Groups are presented by category, i.e. `desktop` or `kernel`
+    #[serde(default)]
+    pub category: Option<String>,
+
+    /// Position within the category, lowest first
+    #[serde(default)]
+    pub sort_order: i32,
+
+    /// A group of the category must be chosen, i.e. a kernel
+    #[serde(default)]
+    pub category_required: bool,
+
+    /// Never presented, neither in a category nor as hardware support, only
+    /// pulled in as a dependency
+    #[serde(default)]
+    pub hidden: bool,
+
+    /// Always installed, so never offered as a choice
+    #[serde(default)]
+    pub mandatory: bool,
+
+    /// Suggested when the hardware satisfies any of these conditions
+    #[serde(default)]
+    pub hardware: Vec<hardware::Match>,
+}
+
+impl Group {
+    /// Is the group available for the architecture?
+    pub fn supports(&self, architecture: &str) -> bool {
+        self.architectures.is_empty() || self.architectures.iter().any(|a| a == architecture)
+    }
+}
+
+/// Why a package is part of a [`Resolution`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// Groups requiring the package
+    pub required_by: BTreeSet<String>,
+
+    /// Groups recommending the package
+    pub recommended_by: BTreeSet<String>,
+}
+
+impl Provenance {
+    /// Is the package required, rather than only recommended?
+    pub fn is_required(&self) -> bool {
+        !self.required_by.is_empty()
+    }
+
+    /// All groups bringing in the package
+    pub fn groups(&self) -> impl Iterator<Item = &'_ String> {
+        self.required_by.union(&self.recommended_by)
+    }
+}
+
+/// The fully resolved closure of some selected groups
+#[derive(Debug, Default, Clone)]
+pub struct Resolution {
+    /// Every group in the closure, dependencies before their dependents
+    pub groups: Vec<String>,
+
+    /// Every required and recommended package, and where it came from
+    pub packages: BTreeMap<String, Provenance>,
+
+    /// Optional packages offered by the groups, and which groups offer them
+    pub optional: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Resolution {
+    /// Names of all packages to install
+    pub fn names(&self) -> BTreeSet<String> {
+        self.packages.keys().cloned().collect()
+    }
 }
 
 impl Display for Group {
@@ -59,9 +213,20 @@ impl FromStr for Group {
 }
 
 /// Simple selections management
-#[derive(Default)]
 pub struct Manager {
     groups: BTreeMap<String, Group>,
+
+    /// Architecture groups are resolved for
+    architecture: String,
+}
+
+impl Default for Manager {
+    fn default() -> Self {
+        Self {
+            groups: BTreeMap::new(),
+            architecture: std::env::consts::ARCH.into(),
+        }
+    }
 }
 
 impl Manager {
@@ -77,9 +242,55 @@ impl Manager {
                 .into_iter()
                 .map(|g| (g.name.clone(), g))
                 .collect::<BTreeMap<_, _>>(),
+            ..self
+        }
+    }
+
+    /// Resolve for another architecture than the host's
+    pub fn with_architecture(self, architecture: impl Into<String>) -> Self {
+        Self {
+            architecture: architecture.into(),
+            ..self
         }
     }
 
+    /// Load the groups of the system, and any overrides
+    pub fn load() -> Result<Self, Error> {
+        Self::load_from(SYSTEM_DIR, OVERRIDE_DIR)
+    }
+
+    /// Load all groups in `dir`, replacing them with those in `overrides`.
+    /// Either may not exist, but between them there must be some groups.
+    pub fn load_from(dir: impl AsRef<Path>, overrides: impl AsRef<Path>) -> Result<Self, Error> {
+        let (dir, overrides) = (dir.as_ref(), overrides.as_ref());
+        let mut groups = BTreeMap::new();
+        for dir in [dir, overrides] {
+            if dir.is_dir() {
+                groups.extend(read_dir(dir)?);
+            }
+        }
+        if groups.is_empty() {
+            return Err(Error::NoGroups(dir.into(), overrides.into()));
+        }
+
+        let manager = Self::new().with_groups(groups.into_values().map(|(_, group)| group));
+        manager.check_depends()?;
+        Ok(manager)
+    }
+
+    /// Ensure every dependency refers to a known group
+    fn check_depends(&self) -> Result<(), Error> {
+        for group in self.groups.values() {
+            if let Some(depends) = group.depends.iter().find(|d| !self.groups.contains_key(*d)) {
+                return Err(Error::UnknownDependency {
+                    group: group.name.clone(),
+                    depends: depends.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
     /// Add a group to the manager
     pub fn insert(&mut self, g: Group) {
         self.groups.insert(g.name.clone(), g);
@@ -90,38 +301,231 @@ impl Manager {
         self.groups.values()
     }
 
-    /// privatwly recurse for string deps
-    fn get_deps(&self, name: &str) -> Result<Vec<String>, Error> {
+    /// Groups installed regardless of the user's choices
+    pub fn mandatory(&self) -> impl Iterator<Item = &'_ Group> {
+        self.groups.values().filter(|g| g.mandatory)
+    }
+
+    /// The chosen IDs, along with those of the mandatory groups
+    pub fn with_mandatory<'a, I: IntoIterator<Item = &'a str>>(&'a self, ids: I) -> Vec<&'a str> {
+        self.mandatory().map(|g| g.name.as_str()).chain(ids).collect()
+    }
+
+    /// Groups supporting the given hardware, for this architecture, to be offered
+    /// to the user (so never hidden ones)
+    pub fn detect<'a>(&'a self, hardware: &Hardware) -> Vec<&'a Group> {
+        self.groups
+            .values()
+            .filter(|g| !g.hidden && g.supports(&self.architecture) && g.hardware.iter().any(|m| m.matches(hardware)))
+            .collect()
+    }
+
+    /// Categories of the groups that can be chosen
+    pub fn categories(&self) -> BTreeSet<&'_ str> {
+        self.groups
+            .values()
+            .filter(|g| !g.hidden && !g.mandatory)
+            .filter_map(|g| g.category.as_deref())
+            .collect()
+    }
+
+    /// Must a group of the category be chosen?
+    pub fn is_required(&self, category: &str) -> bool {
+        self.category(category).iter().any(|g| g.category_required)
+    }
+
+    /// Groups of the category which can be chosen, in sort order
+    pub fn category<'a>(&'a self, category: &'a str) -> Vec<&'a Group> {
+        let mut groups = self
+            .groups
+            .values()
+            .filter(|g| !g.hidden && !g.mandatory && g.category.as_deref() == Some(category))
+            .collect::<Vec<_>>();
+        groups.sort_by_key(|g| (g.sort_order, g.name.clone()));
+        groups
+    }
+
+    /// Depth-first visit of `name` and its dependencies, appending them to `order`
+    /// once all of their own dependencies are there. `path` is the current chain,
+    /// used to detect cycles.
+    fn visit(&self, name: &str, path: &mut Vec<String>, order: &mut Vec<String>) -> Result<(), Error> {
+        if order.iter().any(|n| n == name) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|n| n == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name.into());
+            return Err(Error::Cycle(cycle));
+        }
         let group = self.groups.get(name).ok_or_else(|| Error::UnknownGroup(name.into()))?;
-        let mut depends = group.depends.clone();
 
-        // Recursively build parent deps
+        // Dependencies for other architectures are skipped, requested groups must match
+        if !group.supports(&self.architecture) {
+            if path.is_empty() {
+                return Err(Error::UnsupportedArchitecture {
+                    group: name.into(),
+                    architecture: self.architecture.clone(),
+                });
+            }
+            return Ok(());
+        }
+
+        path.push(name.into());
         for parent in group.depends.iter() {
-            let parent_deps = self.get_deps(parent)?;
-            depends.extend(parent_deps)
+            self.visit(parent, path, order)?;
+        }
+        path.pop();
+        order.push(name.into());
+        Ok(())
+    }
+
+    /// Resolve the selected IDs into their full closure
+    pub fn resolve<'a, I: IntoIterator<Item = &'a str>>(&self, ids: I) -> Result<Resolution, Error> {
+        let mut order = vec![];
+        for item in ids.into_iter() {
+            self.visit(item, &mut vec![], &mut order)?;
+        }
+
+        let groups = order.iter().filter_map(|id| self.groups.get(id)).collect::<Vec<_>>();
+        for group in groups.iter() {
+            if let Some(other) = groups.iter().find(|g| group.conflicts.contains(&g.name)) {
+                return Err(Error::Conflict(group.name.clone(), other.name.clone()));
+            }
+        }
+
+        let mut resolution = Resolution {
+            groups: order.clone(),
+            ..Default::default()
+        };
+        for group in groups {
+            for name in group.required.iter() {
+                let provenance = resolution.packages.entry(name.clone()).or_default();
+                provenance.required_by.insert(group.name.clone());
+            }
+            for name in group.recommended.iter() {
+                let provenance = resolution.packages.entry(name.clone()).or_default();
+                provenance.recommended_by.insert(group.name.clone());
+            }
+            for name in group.optional.iter() {
+                resolution
+                    .optional
+                    .entry(name.clone())
+                    .or_default()
+                    .insert(group.name.clone());
+            }
+        }
+        // Installed anyway, so no longer optional
+        resolution
+            .optional
+            .retain(|name, _| !resolution.packages.contains_key(name));
+
+        Ok(resolution)
+    }
+
+    /// Ensure every package of the selected IDs (and their dependencies) is in the index,
+    /// reporting those missing by the group requiring them
+    pub fn validate<'a, I: IntoIterator<Item = &'a str>>(&self, ids: I, index: &Index) -> Result<(), Error> {
+        let resolution = self.resolve(ids)?;
+        let mut unavailable = BTreeMap::<String, BTreeSet<String>>::new();
+        for name in index.missing(resolution.packages.keys().map(|n| n.as_str())) {
+            for group in resolution.packages[name].groups() {
+                unavailable.entry(group.clone()).or_default().insert(name.to_owned());
+            }
+        }
+        if unavailable.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Unavailable(unavailable))
         }
+    }
 
-        Ok(depends)
+    /// Ensure every exclusion removes something from the `selected` packages, and
+    /// none removes a package required by a mandatory group (or its dependencies)
+    pub fn check_exclusions<'a, I: IntoIterator<Item = &'a str>>(
+        &self,
+        selected: &BTreeSet<String>,
+        exclusions: I,
+    ) -> Result<(), Error> {
+        let mandatory = self.resolve(self.mandatory().map(|g| g.name.as_str()))?;
+        for exclusion in exclusions {
+            if !selected.iter().any(|p| excludes(exclusion, p)) {
+                return Err(Error::UnmatchedExclusion(exclusion.into()));
+            }
+            let required = mandatory
+                .packages
+                .iter()
+                .filter(|(name, _)| excludes(exclusion, name))
+                .find_map(|(_, p)| p.required_by.first());
+            if let Some(group) = required {
+                return Err(Error::RequiredPackage {
+                    package: exclusion.into(),
+                    group: group.clone(),
+                });
+            }
+        }
+        Ok(())
     }
 
     /// Given the selected IDs, what are the total selections?
     pub fn selections_with<'a, I: IntoIterator<Item = &'a str>>(&'a self, ids: I) -> Result<BTreeSet<String>, Error> {
-        let mut selected_ids = BTreeSet::new();
-        for item in ids.into_iter() {
-            let deps = self.get_deps(item)?;
-            selected_ids.extend(deps);
-            selected_ids.insert(item.into());
+        Ok(self.resolve(ids)?.names())
+    }
+}
+
+/// Whether the `exclusion` removes the selected `package`, matching it either
+/// as written or as the name of a provider, i.e. `bash` matches `binary(bash)`
+pub fn excludes(exclusion: &str, package: &str) -> bool {
+    package == exclusion
+        || package
+            .split_once('(')
+            .and_then(|(_, name)| name.strip_suffix(')'))
+            .is_some_and(|name| name == exclusion)
+}
+
+/// Read all `*.json` groups in `dir` by name, along with the file defining them
+fn read_dir(dir: &Path) -> Result<BTreeMap<String, (PathBuf, Group)>, Error> {
+    let mut paths = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut groups = BTreeMap::<String, (PathBuf, Group)>::new();
+    for path in paths {
+        let group = serde_json::from_str::<Group>(&fs::read_to_string(&path)?)
+            .map_err(|e| Error::InvalidGroup(path.clone(), e))?;
+        if let Some((first, _)) = groups.get(&group.name) {
+            return Err(Error::DuplicateGroup {
+                name: group.name,
+                first: first.clone(),
+                second: path,
+            });
         }
-        let core = selected_ids.into_iter().filter_map(|id| self.groups.get(&id));
-        Ok(core.flat_map(|g| g.required.clone()).collect::<BTreeSet<_>>())
+        groups.insert(group.name.clone(), (path, group));
     }
+    Ok(groups)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
+    use std::{path::Path, str::FromStr};
 
-    use crate::selections::{Group, Manager};
+    use fs_err as fs;
+    use system::hardware::{Hardware, PciDevice};
+
+    use crate::{
+        index::{Index, Package},
+        selections::{Error, Group, Manager},
+    };
+
+    fn group(name: &str, depends: &[&str], required: &[&str]) -> Group {
+        Group {
+            name: name.into(),
+            depends: depends.iter().map(|d| d.to_string()).collect(),
+            required: required.iter().map(|r| r.to_string()).collect(),
+            ..Default::default()
+        }
+    }
 
     #[test]
     fn test_decode() {
@@ -136,4 +540,266 @@ mod tests {
         assert_eq!(pkgs_partial.len(), 35);
         assert_eq!(pkgs.len(), 38);
     }
+
+    #[test]
+    fn test_validate() {
+        let manager = Manager::new().with_groups([
+            group("base", &[], &["binary(bash)", "binary(zsh)"]),
+            group("develop", &["base"], &["binary(git)", "nano"]),
+        ]);
+        let index = Index::from_iter([
+            Package {
+                name: "bash".into(),
+                providers: ["binary(bash)".into()].into(),
+                ..Default::default()
+            },
+            Package {
+                name: "nano".into(),
+                ..Default::default()
+            },
+        ]);
+
+        let Err(Error::Unavailable(unavailable)) = manager.validate(["develop"], &index) else {
+            panic!("expected unavailable packages");
+        };
+        assert_eq!(unavailable["base"], ["binary(zsh)".to_string()].into());
+        assert_eq!(unavailable["develop"], ["binary(git)".to_string()].into());
+        assert_eq!(
+            Error::Unavailable(unavailable).to_string(),
+            "packages not available: base: binary(zsh); develop: binary(git)"
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let manager = Manager::new().with_architecture("x86_64").with_groups([
+            group("base", &[], &["bash"]),
+            Group {
+                recommended: vec!["nano".into(), "bash".into()],
+                optional: vec!["vim".into(), "nano".into()],
+                ..group("develop", &["base"], &["git"])
+            },
+            Group {
+                architectures: vec!["aarch64".into()],
+                ..group("firmware-arm", &[], &["u-boot"])
+            },
+            group("desktop", &["develop", "firmware-arm"], &["gdm"]),
+        ]);
+
+        let resolution = manager.resolve(["desktop"]).unwrap();
+        assert_eq!(resolution.groups, ["base", "develop", "desktop"]);
+        assert_eq!(
+            resolution.names(),
+            ["bash", "gdm", "git", "nano"].map(String::from).into()
+        );
+        assert!(resolution.packages["bash"].is_required());
+        assert_eq!(
+            resolution.packages["bash"].recommended_by,
+            ["develop".to_string()].into()
+        );
+        assert!(!resolution.packages["nano"].is_required());
+        assert_eq!(resolution.optional.keys().collect::<Vec<_>>(), ["vim"]);
+
+        assert!(matches!(
+            manager.resolve(["firmware-arm"]),
+            Err(Error::UnsupportedArchitecture { .. })
+        ));
+    }
+
+    #[test]
+    fn test_check_exclusions() {
+        let manager = Manager::new().with_groups([
+            group("core", &[], &["glibc"]),
+            Group {
+                mandatory: true,
+                recommended: vec!["nano".into()],
+                ..group("base", &["core"], &["bash"])
+            },
+            group("develop", &["base"], &["git"]),
+        ]);
+        let selected = manager.selections_with(["develop"]).unwrap();
+        assert!(manager.check_exclusions(&selected, ["nano", "git"]).is_ok());
+        assert!(matches!(
+            manager.check_exclusions(&selected, ["glibc"]),
+            Err(Error::RequiredPackage { group, .. }) if group == "core"
+        ));
+        assert!(manager.check_exclusions(&selected, ["bash"]).is_err());
+        assert!(matches!(
+            manager.check_exclusions(&selected, ["vim"]),
+            Err(Error::UnmatchedExclusion(package)) if package == "vim"
+        ));
+
+        // Providers are matched by their name
+        let manager = Manager::new().with_groups([
+            Group {
+                mandatory: true,
+                ..group("base", &[], &["binary(bash)"])
+            },
+            group("develop", &["base"], &["binary(git)"]),
+        ]);
+        let selected = manager.selections_with(["develop"]).unwrap();
+        assert!(manager.check_exclusions(&selected, ["git", "binary(git)"]).is_ok());
+        assert!(matches!(
+            manager.check_exclusions(&selected, ["bash"]),
+            Err(Error::RequiredPackage { group, .. }) if group == "base"
+        ));
+        assert!(matches!(
+            manager.check_exclusions(&selected, ["binary"]),
+            Err(Error::UnmatchedExclusion(_))
+        ));
+    }
+
+    #[test]
+    fn test_cycle() {
+        let manager = Manager::new().with_groups([
+            group("a", &["b"], &[]),
+            group("b", &["c"], &[]),
+            group("c", &["a"], &[]),
+            group("self", &["self"], &[]),
+        ]);
+        let Err(Error::Cycle(cycle)) = manager.resolve(["a"]) else {
+            panic!("expected a cycle");
+        };
+        assert_eq!(cycle, ["a", "b", "c", "a"]);
+        assert!(matches!(manager.resolve(["self"]), Err(Error::Cycle(_))));
+    }
+
+    #[test]
+    fn test_conflict() {
+        let manager = Manager::new().with_groups([
+            group("base", &[], &[]),
+            Group {
+                conflicts: vec!["cosmic".into()],
+                ..group("gnome", &["base"], &[])
+            },
+            group("cosmic", &["base"], &[]),
+        ]);
+        assert!(manager.resolve(["gnome"]).is_ok());
+        assert!(matches!(
+            manager.resolve(["cosmic", "gnome"]),
+            Err(Error::Conflict(a, b)) if a == "gnome" && b == "cosmic"
+        ));
+    }
+
+    #[test]
+    fn test_detect() {
+        let decode = |json: &str| Group::from_str(json).unwrap();
+        let manager = Manager::new().with_architecture("x86_64").with_groups([
+            decode(include_str!("../../../selections/hardware-intel.json")),
+            decode(include_str!("../../../selections/hardware-nvidia.json")),
+            decode(include_str!("../../../selections/hardware-laptop.json")),
+            Group {
+                architectures: vec!["aarch64".into()],
+                hardware: vec![Default::default()],
+                ..group("arm-firmware", &[], &[])
+            },
+            Group {
+                hidden: true,
+                hardware: vec![Default::default()],
+                ..group("firmware-common", &[], &[])
+            },
+        ]);
+        let hardware = Hardware {
+            cpu_vendor: Some("GenuineIntel".into()),
+            pci: vec![PciDevice {
+                vendor: 0x10de,
+                device: 0x2484,
+                class: 0x030000,
+            }],
+            ..Default::default()
+        };
+        let detected = manager
+            .detect(&hardware)
+            .iter()
+            .map(|g| g.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(detected, ["hardware-intel", "hardware-nvidia"]);
+        assert!(manager.detect(&Hardware::default()).is_empty());
+    }
+
+    #[test]
+    fn test_no_groups() {
+        let root = tempfile::tempdir().unwrap();
+        let (system, overrides) = (root.path().join("system"), root.path().join("overrides"));
+        assert!(matches!(
+            Manager::load_from(&system, &overrides),
+            Err(Error::NoGroups(..))
+        ));
+        fs::create_dir_all(&system).unwrap();
+        assert!(matches!(
+            Manager::load_from(&system, &overrides),
+            Err(Error::NoGroups(..))
+        ));
+    }
+
+    #[test]
+    fn test_mandatory() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../selections");
+        let manager = Manager::load_from(&dir, dir.join("missing")).unwrap();
+        assert_eq!(manager.with_mandatory(["develop"]), ["base", "develop"]);
+        assert!(manager.is_required("kernel"));
+        assert!(!manager.is_required("desktop"));
+        assert!(!manager.is_required("server"));
+    }
+
+    #[test]
+    fn test_load_from() {
+        let root = tempfile::tempdir().unwrap();
+        let (system, overrides) = (root.path().join("system"), root.path().join("overrides"));
+        fs::create_dir_all(&system).unwrap();
+        fs::create_dir_all(&overrides).unwrap();
+        let write = |dir: &Path, file: &str, json: &str| fs::write(dir.join(file), json).unwrap();
+
+        write(
+            &system,
+            "base.json",
+            r#"{"name": "base", "summary": "Base", "description": "", "required": ["bash"], "hidden": true}"#,
+        );
+        write(
+            &system,
+            "gnome.json",
+            r#"{"name": "gnome", "summary": "GNOME", "description": "", "depends": ["base"], "required": [], "category": "desktop", "sort_order": 1}"#,
+        );
+        write(
+            &overrides,
+            "cosmic.json",
+            r#"{"name": "cosmic", "summary": "COSMIC", "description": "", "depends": ["base"], "required": [], "category": "desktop"}"#,
+        );
+        write(
+            &overrides,
+            "base.json",
+            r#"{"name": "base", "summary": "Base", "description": "", "required": ["zsh"]}"#,
+        );
+
+        let manager = Manager::load_from(&system, &overrides).unwrap();
+        let desktops = manager
+            .category("desktop")
+            .iter()
+            .map(|g| g.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(desktops, ["cosmic", "gnome"]);
+        assert_eq!(manager.selections_with(["base"]).unwrap(), ["zsh".to_string()].into());
+
+        // Another file can't define the same group
+        write(
+            &system,
+            "gnome2.json",
+            r#"{"name": "gnome", "summary": "", "description": "", "required": []}"#,
+        );
+        assert!(matches!(
+            Manager::load_from(&system, &overrides),
+            Err(Error::DuplicateGroup { .. })
+        ));
+        fs::remove_file(system.join("gnome2.json")).unwrap();
+
+        write(
+            &system,
+            "kde.json",
+            r#"{"name": "kde", "summary": "", "description": "", "depends": ["qt"], "required": []}"#,
+        );
+        assert!(matches!(
+            Manager::load_from(&system, &overrides),
+            Err(Error::UnknownDependency { depends, .. }) if depends == "qt"
+        ));
+    }
 }
@@ -0,0 +1,277 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Bootloader installation (systemd-boot)
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use super::{Context, Error};
+
+/// Where kernels are installed within the target, as `<version>/vmlinuz`
+const KERNEL_DIR: &str = "usr/lib/kernel";
+
+/// Install systemd-boot to the ESP and write Boot Loader Specification
+/// entries for every kernel installed in the target.
+#[derive(Debug)]
+pub struct InstallBootloader {
+    /// Whether an XBOOTLDR partition is mounted at `/boot`
+    pub(crate) xbootldr: bool,
+
+    /// How the kernel finds `/`, i.e. `PARTUUID=...` or the unlocked device
+    pub(crate) root: String,
+
+    /// LUKS container to unlock before `/` can be mounted
+    pub(crate) luks_uuid: Option<String>,
+
+    /// btrfs subvolume mounted as `/`
+    pub(crate) subvolume: Option<String>,
+
+    /// Update the firmware's EFI variables (boot order)
+    pub(crate) efi_variables: bool,
+}
+
+/// A kernel found in the target root
+#[derive(Debug)]
+struct Kernel {
+    version: String,
+    image: PathBuf,
+    initrds: Vec<PathBuf>,
+}
+
+impl<'a> InstallBootloader {
+    pub(super) fn title(&self) -> String {
+        "Install bootloader".into()
+    }
+
+    pub(super) fn describe(&self) -> String {
+        "systemd-boot".into()
+    }
+
+    pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
+        let root = context.root();
+
+        let mut cmd = Command::new("chroot");
+        cmd.arg(root);
+        cmd.args(["bootctl", "install", "--esp-path=/efi"]);
+        if self.xbootldr {
+            cmd.arg("--boot-path=/boot");
+        }
+        if !self.efi_variables {
+            cmd.arg("--no-variables");
+        }
+        context.run_command_captured(&mut cmd, None)?;
+
+        // Entries + kernels live on XBOOTLDR when present, otherwise the ESP
+        let boot = if self.xbootldr {
+            root.join("boot")
+        } else {
+            root.join("efi")
+        };
+        let kernels = discover_kernels(context, &root.join(KERNEL_DIR))?;
+        if kernels.is_empty() {
+            return Err(Error::NoKernel);
+        }
+
+        let entry_token = context
+            .read_to_string(&root.join("etc").join("machine-id"))?
+            .trim()
+            .to_string();
+        let title = os_name(context, root);
+        let entries = boot.join("loader").join("entries");
+        context.create_dir_all(&entries)?;
+
+        for kernel in kernels {
+            log::info!("Installing boot entry for kernel {}", kernel.version);
+            let dest = boot.join(&entry_token).join(&kernel.version);
+            context.create_dir_all(&dest)?;
+            context.copy_file(&kernel.image, &dest.join("linux"))?;
+            for initrd in kernel.initrds.iter() {
+                if let Some(name) = initrd.file_name() {
+                    context.copy_file(initrd, &dest.join(name))?;
+                }
+            }
+
+            let path = entries.join(format!("{entry_token}-{}.conf", kernel.version));
+            context.write_file(&path, &self.entry(&title, &entry_token, &kernel))?;
+        }
+
+        Ok(())
+    }
+
+    /// Kernel command line, locating (and possibly unlocking) `/`
+    fn cmdline(&self) -> String {
+        let mut cmdline = vec![];
+        if let Some(uuid) = self.luks_uuid.as_ref() {
+            cmdline.push(format!("rd.luks.uuid={uuid}"));
+        }
+        cmdline.push(format!("root={}", self.root));
+        if let Some(subvolume) = self.subvolume.as_ref() {
+            cmdline.push(format!("rootflags=subvol={subvolume}"));
+        }
+        cmdline.push("rw".into());
+        cmdline.join(" ")
+    }
+
+    /// Boot Loader Specification entry for the kernel, as copied below `/<entry_token>/<version>`
+    fn entry(&self, title: &str, entry_token: &str, kernel: &Kernel) -> String {
+        let dir = format!("/{entry_token}/{}", kernel.version);
+        let mut entry = vec![
+            format!("title {title}"),
+            format!("version {}", kernel.version),
+            format!("machine-id {entry_token}"),
+            format!("linux {dir}/linux"),
+        ];
+        for name in kernel.initrds.iter().filter_map(|i| i.file_name()) {
+            entry.push(format!("initrd {dir}/{}", name.to_string_lossy()));
+        }
+        entry.push(format!("options {}", self.cmdline()));
+        entry.join("\n") + "\n"
+    }
+}
+
+/// Find all kernels (and their initrds) in the given directory, ordered by version
+fn discover_kernels<'a>(context: &'a impl Context<'a>, dir: &Path) -> Result<Vec<Kernel>, Error> {
+    let versions = match context.read_dir(dir) {
+        Err(Error::IO(e)) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        res => res?,
+    };
+
+    let mut kernels = vec![];
+    for path in versions {
+        let files = match context.read_dir(&path) {
+            Err(Error::IO(e)) if e.kind() == io::ErrorKind::NotADirectory => continue,
+            res => res?,
+        };
+        let image = path.join("vmlinuz");
+        if !files.contains(&image) {
+            continue;
+        }
+        let Some(version) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+            continue;
+        };
+        let initrds = files
+            .into_iter()
+            .filter(|p| p.file_name().is_some_and(|n| n.to_string_lossy().starts_with("initrd")))
+            .collect();
+        kernels.push(Kernel {
+            version,
+            image,
+            initrds,
+        });
+    }
+    Ok(kernels)
+}
+
+/// Pretty name of the target OS, from its `os-release`
+fn os_name<'a>(context: &'a impl Context<'a>, root: &Path) -> String {
+    ["etc/os-release", "usr/lib/os-release"]
+        .iter()
+        .filter_map(|p| context.read_to_string(&root.join(p)).ok())
+        .find_map(|contents| {
+            contents
+                .lines()
+                .find_map(|l| l.strip_prefix("PRETTY_NAME=").map(|v| v.trim_matches('"').to_string()))
+        })
+        .unwrap_or_else(|| "Serpent OS".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use fs_err as fs;
+
+    use super::{discover_kernels, InstallBootloader, Kernel};
+    use crate::steps::{context::tests::TestContext, InstallLog};
+
+    fn bootloader(luks_uuid: Option<&str>, subvolume: Option<&str>) -> InstallBootloader {
+        InstallBootloader {
+            xbootldr: false,
+            root: match luks_uuid {
+                Some(uuid) => format!("/dev/mapper/luks-{uuid}"),
+                None => "PARTUUID=1234".into(),
+            },
+            luks_uuid: luks_uuid.map(Into::into),
+            subvolume: subvolume.map(Into::into),
+            efi_variables: true,
+        }
+    }
+
+    #[test]
+    fn test_discover_kernels() {
+        let dir = tempfile::tempdir().unwrap();
+        let context = TestContext {
+            root: dir.path().to_path_buf(),
+            install_log: InstallLog::open(dir.path().join("install.log")).unwrap(),
+        };
+        let kernels = dir.path().join("kernels");
+        assert!(discover_kernels(&context, &kernels).unwrap().is_empty());
+
+        for (version, files) in [
+            ("6.12.1", &["vmlinuz", "initrd-b", "initrd-a", "config"][..]),
+            ("6.6.60", &["vmlinuz"][..]),
+            ("broken", &["initrd"][..]),
+        ] {
+            fs::create_dir_all(kernels.join(version)).unwrap();
+            for file in files {
+                fs::write(kernels.join(version).join(file), "").unwrap();
+            }
+        }
+        fs::write(kernels.join("stray"), "").unwrap();
+
+        let found = discover_kernels(&context, &kernels).unwrap();
+        assert_eq!(
+            found.iter().map(|k| k.version.as_str()).collect::<Vec<_>>(),
+            ["6.12.1", "6.6.60"]
+        );
+        assert_eq!(found[0].image, kernels.join("6.12.1/vmlinuz"));
+        assert_eq!(
+            found[0].initrds,
+            [kernels.join("6.12.1/initrd-a"), kernels.join("6.12.1/initrd-b")]
+        );
+        assert!(found[1].initrds.is_empty());
+    }
+
+    #[test]
+    fn test_entry() {
+        let kernel = Kernel {
+            version: "6.12.1".into(),
+            image: PathBuf::from("/usr/lib/kernel/6.12.1/vmlinuz"),
+            initrds: vec![
+                PathBuf::from("/usr/lib/kernel/6.12.1/initrd-a"),
+                PathBuf::from("/usr/lib/kernel/6.12.1/initrd-b"),
+            ],
+        };
+        assert_eq!(
+            bootloader(None, None).entry("Serpent OS", "abcd", &kernel),
+            "title Serpent OS\n\
+             version 6.12.1\n\
+             machine-id abcd\n\
+             linux /abcd/6.12.1/linux\n\
+             initrd /abcd/6.12.1/initrd-a\n\
+             initrd /abcd/6.12.1/initrd-b\n\
+             options root=PARTUUID=1234 rw\n"
+        );
+    }
+
+    #[test]
+    fn test_cmdline() {
+        assert_eq!(
+            bootloader(Some("5678"), None).cmdline(),
+            "rd.luks.uuid=5678 root=/dev/mapper/luks-5678 rw"
+        );
+        assert_eq!(
+            bootloader(None, Some("@")).cmdline(),
+            "root=PARTUUID=1234 rootflags=subvol=@ rw"
+        );
+        assert_eq!(
+            bootloader(Some("5678"), Some("@")).cmdline(),
+            "rd.luks.uuid=5678 root=/dev/mapper/luks-5678 rootflags=subvol=@ rw"
+        );
+    }
+}
@@ -0,0 +1,59 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Btrfs subvolume management
+
+use std::{path::PathBuf, process::Command};
+
+use super::{Context, Error};
+
+/// Create subvolumes at the top level of a freshly formatted btrfs filesystem
+#[derive(Debug)]
+pub struct CreateSubvolumes {
+    /// Device carrying the btrfs filesystem
+    pub(crate) device: PathBuf,
+
+    /// Temporary mountpoint for the top level subvolume
+    pub(crate) mountpoint: PathBuf,
+
+    /// Subvolume names to create
+    pub(crate) names: Vec<String>,
+}
+
+impl<'a> CreateSubvolumes {
+    pub(super) fn title(&self) -> String {
+        "Create btrfs subvolumes".into()
+    }
+
+    pub(super) fn describe(&self) -> String {
+        self.names.join(", ")
+    }
+
+    pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
+        log::info!("Creating subvolumes {:?} on {}", self.names, self.device.display());
+
+        context.create_dir_all(&self.mountpoint)?;
+        let mut cmd = Command::new("mount");
+        cmd.args(["-o", "subvolid=5"]);
+        cmd.arg(&self.device);
+        cmd.arg(&self.mountpoint);
+        context.run_command_captured(&mut cmd, None)?;
+
+        let created = self.names.iter().try_for_each(|name| {
+            let mut cmd = Command::new("btrfs");
+            cmd.args(["subvolume", "create"]);
+            cmd.arg(self.mountpoint.join(name));
+            context.run_command_captured(&mut cmd, None).map(|_| ())
+        });
+
+        // Always release the top level again, even on failure
+        let mut cmd = Command::new("umount");
+        cmd.arg(&self.mountpoint);
+        let unmounted = context.run_command_captured(&mut cmd, None);
+
+        created?;
+        unmounted?;
+        Ok(())
+    }
+}
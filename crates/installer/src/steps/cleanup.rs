@@ -9,15 +9,19 @@
 //!
 //! To that effect we provide a mirror of [`Step`] by way of a Cleanup.
 
-use super::{partitions, Context};
+use super::{encryption, partitions, Context, Step};
 
 /// Encapsulate the cleanup stages
+#[derive(Debug)]
 pub enum Cleanup {
     /// Unmount a mountpoint
     Unmount(Box<partitions::Unmount>),
 
     /// Sync filesystems pre unmount
     Sync(Box<partitions::SyncFS>),
+
+    /// Close an encrypted mapping
+    Close(Box<encryption::CloseEncrypted>),
 }
 
 impl<'a> Cleanup {
@@ -31,11 +35,38 @@ impl<'a> Cleanup {
         Self::Sync(Box::new(partitions::SyncFS {}))
     }
 
+    /// Create new encrypted mapping close stage
+    pub fn close_encrypted(close: encryption::CloseEncrypted) -> Self {
+        Self::Close(Box::new(close))
+    }
+
+    /// Whether this undoes the effect of the given step. Syncing is only
+    /// needed once anything was mounted.
+    pub fn undoes(&self, step: &Step<'_>) -> bool {
+        match (self, step) {
+            (Self::Unmount(u), Step::Mount(m)) => u.mountpoint == m.mountpoint,
+            (Self::Unmount(u), Step::Bind(b)) => u.mountpoint == b.dest,
+            (Self::Sync(_), Step::Mount(_)) => true,
+            (Self::Close(c), Step::OpenEncrypted(o)) => c.name == o.name,
+            _ => false,
+        }
+    }
+
+    /// Return a unique short ID name for the cleanup
+    pub fn name(&self) -> &'static str {
+        match &self {
+            Self::Unmount(_) => "unmount",
+            Self::Sync(_) => "sync-fs",
+            Self::Close(_) => "close-encrypted",
+        }
+    }
+
     /// Return cleanup step title
     pub fn title(&self) -> String {
         match &self {
             Self::Unmount(s) => s.title(),
             Self::Sync(s) => s.title(),
+            Self::Close(s) => s.title(),
         }
     }
 
@@ -44,6 +75,7 @@ impl<'a> Cleanup {
         match &self {
             Self::Unmount(s) => s.describe(),
             Self::Sync(s) => s.describe(),
+            Self::Close(s) => s.describe(),
         }
     }
 
@@ -52,6 +84,46 @@ impl<'a> Cleanup {
         match &self {
             Self::Unmount(s) => Ok(s.execute(context)?),
             Self::Sync(s) => Ok(s.execute(context)?),
+            Self::Close(s) => Ok(s.execute(context)?),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::Cleanup;
+    use crate::steps::{CloseEncrypted, MountPartition, OpenEncrypted, Step, Unmount};
+
+    #[test]
+    fn test_undoes() {
+        let mount = Step::mount(MountPartition {
+            device: "/dev/sda3".into(),
+            mountpoint: "/tmp/lichen/home".into(),
+            options: None,
+        });
+        let open = Step::open_encrypted(OpenEncrypted {
+            device: "/dev/sda2".into(),
+            name: "luks-root".into(),
+            passphrase: "hunter2".into(),
+        });
+        let unmount = |mountpoint: &str| {
+            Cleanup::unmount(Unmount {
+                mountpoint: PathBuf::from(mountpoint),
+            })
+        };
+
+        assert!(unmount("/tmp/lichen/home").undoes(&mount));
+        assert!(!unmount("/tmp/lichen").undoes(&mount));
+        assert!(!unmount("/tmp/lichen/home").undoes(&open));
+        assert!(Cleanup::sync_fs().undoes(&mount));
+        assert!(!Cleanup::sync_fs().undoes(&open));
+
+        let close = Cleanup::close_encrypted(CloseEncrypted {
+            name: "luks-root".into(),
+        });
+        assert!(close.undoes(&open));
+        assert!(!close.undoes(&mount));
+    }
+}
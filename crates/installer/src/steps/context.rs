@@ -6,10 +6,108 @@
 
 use std::{
     fmt::Debug,
-    path::PathBuf,
-    process::{Command, Output},
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Output, Stdio},
+    sync::{mpsc, Mutex},
+    thread,
 };
 
+use fs_err::{self as fs, os::unix::fs::OpenOptionsExt};
+use system::disk::{self, Disk, Layout, Table};
+
+/// Persistent log of every command run during the installation, and its output.
+/// Input is never logged as it may carry secrets.
+#[derive(Debug)]
+pub struct InstallLog {
+    path: PathBuf,
+    file: Mutex<fs::File>,
+}
+
+impl InstallLog {
+    /// Default location, on the host
+    pub const DEFAULT_PATH: &'static str = "/run/lichen/install.log";
+
+    /// Where the log is copied to in the target
+    pub const TARGET_PATH: &'static str = "var/log/lichen.log";
+
+    /// Open the log for appending, so resumed runs are kept together
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Command lines may be sensitive
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(&path)?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Location of the log
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a line to the log
+    pub fn line(&self, line: impl AsRef<str>) {
+        self.append(|file| writeln!(file, "{}", line.as_ref()));
+    }
+
+    /// Append a command, its captured output (if any) and exit status
+    pub fn command(&self, cmd: &Command, output: Option<(&[u8], &[u8])>, status: Option<ExitStatus>) {
+        self.append(|file| {
+            writeln!(file, "$ {cmd:?}")?;
+            if let Some((stdout, stderr)) = output {
+                file.write_all(stdout)?;
+                file.write_all(stderr)?;
+            }
+            match status {
+                Some(status) => writeln!(file, "[{status}]"),
+                None => writeln!(file, "[failed to run]"),
+            }
+        });
+    }
+
+    /// Logging must never fail the installation
+    fn append(&self, f: impl FnOnce(&mut fs::File) -> io::Result<()>) {
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        if let Err(e) = f(&mut file).and_then(|_| file.flush()) {
+            log::warn!("Failed to write to {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// Send each line read to the channel, tagged as stderr or not
+fn forward_lines(reader: impl Read, is_stderr: bool, tx: mpsc::Sender<(bool, String)>) {
+    for line in BufReader::new(reader).lines().map_while(Result::ok) {
+        if tx.send((is_stderr, line)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Convert a failed exit status into [`super::Error::CommandFailed`]
+fn check(cmd: &Command, status: ExitStatus, stdout: &[u8], stderr: &[u8]) -> Result<(), super::Error> {
+    if status.success() {
+        return Ok(());
+    }
+    Err(super::Error::CommandFailed {
+        program: cmd.get_program().to_string_lossy().into(),
+        status,
+        stdout: String::from_utf8_lossy(stdout).into(),
+        stderr: String::from_utf8_lossy(stderr).into(),
+    })
+}
+
 /// Context for the steps that are executing
 /// The context provides access to the core installation variables as
 /// well as simplified paths for executing commands in a consistent
@@ -18,10 +116,230 @@ pub trait Context<'a>: Sized + Debug + Send {
     /// Return the root directory of the installation
     fn root(&'a self) -> &'a PathBuf;
 
-    /// Run the command asynchronously via the context
-    fn run_command(&self, cmd: &mut Command) -> Result<(), super::Error>;
+    /// The install log, if one is kept
+    fn install_log(&self) -> Option<&InstallLog> {
+        None
+    }
+
+    /// Report progress to the frontend
+    fn emit(&self, _event: super::Event) {}
+
+    /// Run the command with output going straight to the terminal
+    fn run_command(&self, cmd: &mut Command) -> Result<(), super::Error> {
+        log::trace!("Running: {cmd:?}");
+        let status = cmd.spawn().and_then(|mut ps| ps.wait());
+        if let Some(install_log) = self.install_log() {
+            install_log.command(cmd, None, status.as_ref().ok().copied());
+        }
+        check(cmd, status?, &[], &[])
+    }
 
     /// Run command, capture the output
     /// Accepts optional string to write as stdin
-    fn run_command_captured(&self, cmd: &mut Command, input: Option<&str>) -> Result<Output, super::Error>;
+    fn run_command_captured(&self, cmd: &mut Command, input: Option<&str>) -> Result<Output, super::Error> {
+        log::trace!("Running: {cmd:?}");
+        cmd.stdin(Stdio::piped());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        let output = cmd.spawn().and_then(|mut ps| {
+            let mut stdin = ps.stdin.take().expect("stdin failure");
+            if let Some(input) = input {
+                stdin.write_all(input.as_bytes())?;
+            }
+            drop(stdin);
+            ps.wait_with_output()
+        });
+        if let Some(install_log) = self.install_log() {
+            let captured = output.as_ref().ok().map(|o| (o.stdout.as_slice(), o.stderr.as_slice()));
+            install_log.command(cmd, captured, output.as_ref().ok().map(|o| o.status));
+        }
+
+        let output = output?;
+        check(cmd, output.status, &output.stdout, &output.stderr)?;
+        Ok(output)
+    }
+
+    /// Run command, passing every line of stdout + stderr to `on_line` as it arrives
+    fn run_command_streamed(&self, cmd: &mut Command, on_line: &mut dyn FnMut(&str)) -> Result<(), super::Error> {
+        log::trace!("Running: {cmd:?}");
+        cmd.stdin(Stdio::null());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        let mut ps = match cmd.spawn() {
+            Ok(ps) => ps,
+            Err(e) => {
+                if let Some(install_log) = self.install_log() {
+                    install_log.command(cmd, None, None);
+                }
+                return Err(e.into());
+            }
+        };
+        let stdout = ps.stdout.take().expect("stdout failure");
+        let stderr = ps.stderr.take().expect("stderr failure");
+
+        let (mut out, mut err) = (String::new(), String::new());
+        let (tx, rx) = mpsc::channel();
+        thread::scope(|scope| {
+            let tx_stderr = tx.clone();
+            scope.spawn(move || forward_lines(stdout, false, tx));
+            scope.spawn(move || forward_lines(stderr, true, tx_stderr));
+            for (is_stderr, line) in rx {
+                on_line(&line);
+                let buffer = if is_stderr { &mut err } else { &mut out };
+                buffer.push_str(&line);
+                buffer.push('\n');
+            }
+        });
+        let status = ps.wait();
+
+        if let Some(install_log) = self.install_log() {
+            install_log.command(
+                cmd,
+                Some((out.as_bytes(), err.as_bytes())),
+                status.as_ref().ok().copied(),
+            );
+        }
+        check(cmd, status?, out.as_bytes(), err.as_bytes())
+    }
+
+    /// Replace the partition table of `disk` with a new one, laid out by `build`
+    fn write_partition_table(
+        &self,
+        disk: &Disk,
+        build: &dyn Fn(&mut Table) -> Result<(), disk::Error>,
+    ) -> Result<Layout, super::Error> {
+        let mut table = disk.new_table(false)?;
+        build(&mut table)?;
+        let layout = table.commit()?;
+        if let Some(install_log) = self.install_log() {
+            install_log.line(format!("# New partition table of {}\n{layout}", disk.path.display()));
+        }
+        Ok(layout)
+    }
+
+    /// Read a file into a string
+    fn read_to_string(&self, path: &Path) -> Result<String, super::Error> {
+        Ok(fs::read_to_string(path)?)
+    }
+
+    /// List the paths within a directory, sorted
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, super::Error> {
+        let mut paths = fs::read_dir(path)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Create a directory and all of its parents
+    fn create_dir_all(&self, path: &Path) -> Result<(), super::Error> {
+        Ok(fs::create_dir_all(path)?)
+    }
+
+    /// Write a file, replacing any existing contents
+    fn write_file(&self, path: &Path, contents: &str) -> Result<(), super::Error> {
+        Ok(fs::write(path, contents)?)
+    }
+
+    /// Write a file readable by root alone (0600), replacing any existing contents
+    fn write_private_file(&self, path: &Path, contents: &str) -> Result<(), super::Error> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        // The mode only applies to new files
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+
+    /// Copy a file
+    fn copy_file(&self, from: &Path, to: &Path) -> Result<(), super::Error> {
+        fs::copy(from, to)?;
+        Ok(())
+    }
+
+    /// Remove a file, if it exists
+    fn remove_file(&self, path: &Path) -> Result<(), super::Error> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            res => Ok(res?),
+        }
+    }
+
+    /// Create a symlink at `link` pointing to `target`
+    fn symlink(&self, target: &Path, link: &Path) -> Result<(), super::Error> {
+        Ok(std::os::unix::fs::symlink(target, link)?)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{os::unix::fs::PermissionsExt, path::PathBuf, process::Command};
+
+    use super::{Context, InstallLog};
+    use crate::steps::Error;
+
+    /// Performs everything for real, within `root`
+    #[derive(Debug)]
+    pub(crate) struct TestContext {
+        pub(crate) root: PathBuf,
+        pub(crate) install_log: InstallLog,
+    }
+
+    impl<'a> Context<'a> for TestContext {
+        fn root(&'a self) -> &'a PathBuf {
+            &self.root
+        }
+
+        fn install_log(&self) -> Option<&InstallLog> {
+            Some(&self.install_log)
+        }
+    }
+
+    #[test]
+    fn test_command_failed() {
+        let dir = tempfile::tempdir().unwrap();
+        let context = TestContext {
+            root: dir.path().to_path_buf(),
+            install_log: InstallLog::open(dir.path().join("install.log")).unwrap(),
+        };
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "read secret; echo out; echo err >&2; exit 3"]);
+        let Err(Error::CommandFailed { stdout, stderr, .. }) =
+            context.run_command_captured(&mut cmd, Some("hunter2\n"))
+        else {
+            panic!("expected the command to fail");
+        };
+        assert_eq!(stdout, "out\n");
+        assert_eq!(stderr, "err\n");
+
+        let log = fs_err::read_to_string(context.install_log.path()).unwrap();
+        assert!(log.contains("out\nerr\n"));
+        assert!(!log.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_write_private_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let context = TestContext {
+            root: dir.path().to_path_buf(),
+            install_log: InstallLog::open(dir.path().join("install.log")).unwrap(),
+        };
+
+        // Existing files are tightened too
+        let path = dir.path().join("secret");
+        fs_err::write(&path, "old").unwrap();
+        fs_err::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        context.write_private_file(&path, "new").unwrap();
+
+        let mode = fs_err::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs_err::read_to_string(&path).unwrap(), "new");
+    }
 }
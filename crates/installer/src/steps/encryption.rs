@@ -0,0 +1,149 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! LUKS2 encryption via cryptsetup
+
+use std::{fmt::Debug, path::PathBuf, process::Command};
+
+use super::{Context, Error};
+
+/// Create a new LUKS2 container on a partition
+pub struct EncryptPartition {
+    /// Partition device to encrypt
+    pub(crate) device: PathBuf,
+
+    /// UUID for the new LUKS header
+    pub(crate) uuid: String,
+
+    pub(crate) passphrase: String,
+}
+
+/// Never leak the passphrase into logs (or the journal)
+impl Debug for EncryptPartition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptPartition")
+            .field("device", &self.device)
+            .field("uuid", &self.uuid)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> EncryptPartition {
+    pub(super) fn title(&self) -> String {
+        "Encrypt partition".into()
+    }
+
+    pub(super) fn describe(&self) -> String {
+        format!("{} with LUKS2", self.device.display())
+    }
+
+    pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
+        log::info!("Encrypting {} (LUKS2)", self.device.display());
+
+        let mut cmd = Command::new("cryptsetup");
+        cmd.args([
+            "luksFormat",
+            "--type",
+            "luks2",
+            "--batch-mode",
+            "--key-file",
+            "-",
+            "--uuid",
+        ]);
+        cmd.arg(&self.uuid);
+        cmd.arg(&self.device);
+        context.run_command_captured(&mut cmd, Some(&self.passphrase))?;
+        Ok(())
+    }
+}
+
+/// Unlock a LUKS container to `/dev/mapper/{name}`
+pub struct OpenEncrypted {
+    pub(crate) device: PathBuf,
+    pub(crate) name: String,
+    pub(crate) passphrase: String,
+}
+
+impl Debug for OpenEncrypted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OpenEncrypted")
+            .field("device", &self.device)
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> OpenEncrypted {
+    pub(super) fn title(&self) -> String {
+        "Unlock encrypted partition".into()
+    }
+
+    pub(super) fn describe(&self) -> String {
+        format!("{} as {}", self.device.display(), self.name)
+    }
+
+    pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
+        log::info!("Opening {} as {}", self.device.display(), self.name);
+
+        let mut cmd = Command::new("cryptsetup");
+        cmd.args(["open", "--type", "luks2", "--key-file", "-"]);
+        cmd.arg(&self.device);
+        cmd.arg(&self.name);
+        context.run_command_captured(&mut cmd, Some(&self.passphrase))?;
+        Ok(())
+    }
+}
+
+/// Close an unlocked LUKS mapping
+#[derive(Debug)]
+pub struct CloseEncrypted {
+    pub(crate) name: String,
+}
+
+impl<'a> CloseEncrypted {
+    pub(super) fn title(&self) -> String {
+        "Lock encrypted partition".into()
+    }
+
+    pub(super) fn describe(&self) -> String {
+        self.name.clone()
+    }
+
+    pub(super) fn execute(&self, context: &impl Context<'a>) -> Result<(), Error> {
+        log::info!("Closing {}", self.name);
+
+        let mut cmd = Command::new("cryptsetup");
+        cmd.arg("close");
+        cmd.arg(&self.name);
+        context.run_command_captured(&mut cmd, None)?;
+        Ok(())
+    }
+}
+
+/// Write `/etc/crypttab` so the container is unlocked at boot
+#[derive(Debug)]
+pub struct EmitCrypttab {
+    pub(crate) name: String,
+    pub(crate) uuid: String,
+}
+
+impl<'a> EmitCrypttab {
+    pub(super) fn title(&self) -> String {
+        "Generate crypttab".into()
+    }
+
+    pub(super) fn describe(&self) -> String {
+        self.name.clone()
+    }
+
+    pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
+        let file = context.root().join("etc").join("crypttab");
+        let contents = format!(
+            "# <name>\t<device>\t<password>\t<options>\n{}\tUUID={}\tnone\tluks,discard\n",
+            self.name, self.uuid
+        );
+        context.write_file(&file, &contents)?;
+        Ok(())
+    }
+}
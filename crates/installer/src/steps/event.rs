@@ -0,0 +1,87 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Progress events
+//!
+//! The [`crate::driver::Driver`] and the steps themselves report progress
+//! through [`super::Context::emit`], so every frontend renders the same
+//! information regardless of how it is displayed.
+
+use serde::Serialize;
+
+/// Identifies the step (or cleanup) an event relates to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Stage {
+    /// Position within all steps and cleanups, from 0
+    pub index: usize,
+
+    /// Total number of steps and cleanups
+    pub total: usize,
+
+    /// Whether this is a cleanup, rather than a step
+    pub cleanup: bool,
+
+    pub name: &'static str,
+    pub title: String,
+    pub description: String,
+}
+
+/// Progress of the installation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// A step is about to run
+    Started(Stage),
+
+    /// A step was skipped, as a previous run already completed it
+    Skipped(Stage),
+
+    /// A step completed successfully
+    Finished(Stage),
+
+    /// A step failed
+    Failed { stage: Stage, error: String },
+
+    /// Determinate progress within the current step
+    Progress {
+        /// Completed fraction, from 0.0 to 1.0
+        fraction: f32,
+        message: Option<String>,
+    },
+
+    /// A line of output from the current step
+    Log { line: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, Stage};
+
+    #[test]
+    fn test_serialize() {
+        let stage = Stage {
+            index: 2,
+            total: 10,
+            cleanup: false,
+            name: "install-packages",
+            title: "Install".into(),
+            description: "42 packages".into(),
+        };
+        let json = serde_json::to_value(Event::Failed {
+            stage,
+            error: "moss failed".into(),
+        })
+        .unwrap();
+        assert_eq!(json["event"], "failed");
+        assert_eq!(json["stage"]["name"], "install-packages");
+
+        let json = serde_json::to_value(Event::Progress {
+            fraction: 0.5,
+            message: None,
+        })
+        .unwrap();
+        assert_eq!(json["event"], "progress");
+        assert_eq!(json["fraction"], 0.5);
+    }
+}
@@ -8,7 +8,10 @@ use std::{fmt::Debug, process::ExitStatus};
 use thiserror::Error;
 
 mod context;
-pub use context::Context;
+pub use context::{Context, InstallLog};
+
+mod event;
+pub use event::{Event, Stage};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -21,22 +24,50 @@ pub enum Error {
     #[error("no mountpoint given")]
     NoMountpoint,
 
+    #[error("no kernel found in the target")]
+    NoKernel,
+
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("index: {0}")]
+    Index(#[from] crate::index::Error),
+
+    #[error("disk: {0}")]
+    Disk(#[from] system::disk::Error),
+
     #[error("command `{program}` exited with {status}")]
-    CommandFailed { program: String, status: ExitStatus },
+    CommandFailed {
+        program: String,
+        status: ExitStatus,
+        stdout: String,
+        stderr: String,
+    },
 }
 
 #[derive(Debug)]
 pub enum Step<'a> {
     AddRepo(Box<AddRepo>),
     Bind(Box<BindMount>),
+    ConfigureZram(Box<ConfigureZram>),
+    CreateSubvolumes(Box<CreateSubvolumes>),
+    CreateSwapfile(Box<CreateSwapfile>),
     CreateUser(Box<CreateAccount<'a>>),
-    Format(Box<FormatPartition<'a>>),
+    Encrypt(Box<EncryptPartition>),
+    Format(Box<FormatPartition>),
+    FormatSwap(Box<FormatSwap>),
+    IndexStones(Box<IndexStones>),
+    InstallBootloader(Box<InstallBootloader>),
     Install(Box<InstallPackages>),
-    Mount(Box<MountPartition<'a>>),
+    Mount(Box<MountPartition>),
+    OpenEncrypted(Box<OpenEncrypted>),
+    RecordInstall(Box<RecordInstall>),
+    SaveLog(Box<SaveLog>),
     SetPassword(Box<SetPassword<'a>>),
     SetLocale(Box<SetLocale<'a>>),
     SetMachineID(Box<SetMachineID>),
     SetTimezone(Box<SetTimezone<'a>>),
+    WriteCrypttab(Box<EmitCrypttab>),
     WriteFstab(Box<EmitFstab>),
 }
 
@@ -46,21 +77,61 @@ impl<'a> Step<'a> {
         Self::AddRepo(Box::new(r))
     }
 
+    /// Create btrfs subvolumes
+    pub fn create_subvolumes(c: CreateSubvolumes) -> Self {
+        Self::CreateSubvolumes(Box::new(c))
+    }
+
+    /// Create a swapfile
+    pub fn create_swapfile(c: CreateSwapfile) -> Self {
+        Self::CreateSwapfile(Box::new(c))
+    }
+
+    /// Format a swap partition
+    pub fn format_swap(f: FormatSwap) -> Self {
+        Self::FormatSwap(Box::new(f))
+    }
+
+    /// Configure zram swap
+    pub fn configure_zram() -> Self {
+        Self::ConfigureZram(Box::new(ConfigureZram {}))
+    }
+
     pub fn create_user(u: CreateAccount<'a>) -> Self {
         Self::CreateUser(Box::new(u))
     }
 
+    /// Index a directory of `.stone` packages
+    pub fn index_stones(i: IndexStones) -> Self {
+        Self::IndexStones(Box::new(i))
+    }
+
     pub fn install_packages(p: InstallPackages) -> Self {
         Self::Install(Box::new(p))
     }
 
+    /// Install the bootloader + boot entries
+    pub fn install_bootloader(b: InstallBootloader) -> Self {
+        Self::InstallBootloader(Box::new(b))
+    }
+
     /// Create new FormatPartition step
-    pub fn format(f: FormatPartition<'a>) -> Self {
+    pub fn format(f: FormatPartition) -> Self {
         Self::Format(Box::new(f))
     }
 
+    /// Create a new LUKS2 container
+    pub fn encrypt(e: EncryptPartition) -> Self {
+        Self::Encrypt(Box::new(e))
+    }
+
+    /// Unlock a LUKS2 container
+    pub fn open_encrypted(o: OpenEncrypted) -> Self {
+        Self::OpenEncrypted(Box::new(o))
+    }
+
     /// Create new MountPartition step
-    pub fn mount(m: MountPartition<'a>) -> Self {
+    pub fn mount(m: MountPartition) -> Self {
         Self::Mount(Box::new(m))
     }
 
@@ -89,6 +160,21 @@ impl<'a> Step<'a> {
         Self::SetMachineID(Box::new(SetMachineID {}))
     }
 
+    /// Emit the given crypttab
+    pub fn emit_crypttab(c: EmitCrypttab) -> Self {
+        Self::WriteCrypttab(Box::new(c))
+    }
+
+    /// Record a replayable manifest in the target
+    pub fn record_install(r: RecordInstall) -> Self {
+        Self::RecordInstall(Box::new(r))
+    }
+
+    /// Copy the install log into the target
+    pub fn save_log() -> Self {
+        Self::SaveLog(Box::new(SaveLog {}))
+    }
+
     // Emit the given fstab
     pub fn emit_fstab(f: EmitFstab) -> Self {
         Self::WriteFstab(Box::new(f))
@@ -99,14 +185,25 @@ impl<'a> Step<'a> {
         match &self {
             Step::AddRepo(_) => "add-repo",
             Step::Bind(_) => "bind-mount",
+            Step::ConfigureZram(_) => "configure-zram",
+            Step::CreateSubvolumes(_) => "create-subvolumes",
+            Step::CreateSwapfile(_) => "create-swapfile",
             Step::CreateUser(_) => "create-user",
+            Step::Encrypt(_) => "encrypt-partition",
             Step::Format(_) => "format-partition",
+            Step::FormatSwap(_) => "format-swap",
+            Step::IndexStones(_) => "index-stones",
+            Step::InstallBootloader(_) => "install-bootloader",
             Step::Install(_) => "install-packages",
             Step::Mount(_) => "mount-partition",
+            Step::OpenEncrypted(_) => "open-encrypted",
+            Step::RecordInstall(_) => "record-install",
+            Step::SaveLog(_) => "save-log",
             Step::SetPassword(_) => "set-password",
             Step::SetLocale(_) => "set-locale",
             Step::SetTimezone(_) => "set-timezone",
             Step::SetMachineID(_) => "set-machine-id",
+            Step::WriteCrypttab(_) => "write-crypttab",
             Step::WriteFstab(_) => "write-fstab",
         }
     }
@@ -116,14 +213,25 @@ impl<'a> Step<'a> {
         match &self {
             Step::AddRepo(s) => s.title(),
             Step::Bind(s) => s.title(),
+            Step::ConfigureZram(s) => s.title(),
+            Step::CreateSubvolumes(s) => s.title(),
+            Step::CreateSwapfile(s) => s.title(),
             Step::CreateUser(s) => s.title(),
+            Step::Encrypt(s) => s.title(),
             Step::Format(s) => s.title(),
+            Step::FormatSwap(s) => s.title(),
+            Step::IndexStones(s) => s.title(),
+            Step::InstallBootloader(s) => s.title(),
             Step::Install(s) => s.title(),
             Step::Mount(s) => s.title(),
+            Step::OpenEncrypted(s) => s.title(),
+            Step::RecordInstall(s) => s.title(),
+            Step::SaveLog(s) => s.title(),
             Step::SetPassword(s) => s.title(),
             Step::SetLocale(s) => s.title(),
             Step::SetTimezone(s) => s.title(),
             Step::SetMachineID(s) => s.title(),
+            Step::WriteCrypttab(s) => s.title(),
             Step::WriteFstab(s) => s.title(),
         }
     }
@@ -133,14 +241,25 @@ impl<'a> Step<'a> {
         match &self {
             Step::AddRepo(s) => s.describe(),
             Step::Bind(s) => s.describe(),
+            Step::ConfigureZram(s) => s.describe(),
+            Step::CreateSubvolumes(s) => s.describe(),
+            Step::CreateSwapfile(s) => s.describe(),
             Step::CreateUser(s) => s.describe(),
+            Step::Encrypt(s) => s.describe(),
             Step::Format(s) => s.describe(),
+            Step::FormatSwap(s) => s.describe(),
+            Step::IndexStones(s) => s.describe(),
+            Step::InstallBootloader(s) => s.describe(),
             Step::Install(s) => s.describe(),
             Step::Mount(s) => s.describe(),
+            Step::OpenEncrypted(s) => s.describe(),
+            Step::RecordInstall(s) => s.describe(),
+            Step::SaveLog(s) => s.describe(),
             Step::SetPassword(s) => s.describe(),
             Step::SetLocale(s) => s.describe(),
             Step::SetTimezone(s) => s.describe(),
             Step::SetMachineID(s) => s.describe(),
+            Step::WriteCrypttab(s) => s.describe(),
             Step::WriteFstab(s) => s.describe(),
         }
     }
@@ -150,22 +269,33 @@ impl<'a> Step<'a> {
         match &self {
             Step::AddRepo(s) => Ok(s.execute(context)?),
             Step::Bind(s) => Ok(s.execute(context)?),
+            Step::ConfigureZram(s) => Ok(s.execute(context)?),
+            Step::CreateSubvolumes(s) => Ok(s.execute(context)?),
+            Step::CreateSwapfile(s) => Ok(s.execute(context)?),
             Step::CreateUser(s) => Ok(s.execute(context)?),
+            Step::Encrypt(s) => Ok(s.execute(context)?),
             Step::Format(s) => Ok(s.execute(context)?),
+            Step::FormatSwap(s) => Ok(s.execute(context)?),
+            Step::IndexStones(s) => Ok(s.execute(context)?),
+            Step::InstallBootloader(s) => Ok(s.execute(context)?),
             Step::Install(s) => Ok(s.execute(context)?),
             Step::Mount(s) => Ok(s.execute(context)?),
+            Step::OpenEncrypted(s) => Ok(s.execute(context)?),
+            Step::RecordInstall(s) => Ok(s.execute(context)?),
+            Step::SaveLog(s) => Ok(s.execute(context)?),
             Step::SetPassword(s) => Ok(s.execute(context)?),
             Step::SetLocale(s) => Ok(s.execute(context)?),
             Step::SetTimezone(s) => Ok(s.execute(context)?),
             Step::SetMachineID(s) => Ok(s.execute(context)?),
+            Step::WriteCrypttab(s) => Ok(s.execute(context)?),
             Step::WriteFstab(s) => Ok(s.execute(context)?),
         }
     }
 
-    /// Determine whether an indeterminate progress spinner is needed
-    /// In the CLI frontend this is abused to hide the progressbar when invoking moss.
-    pub fn is_indeterminate(&self) -> bool {
-        !matches!(self, Step::Install(_))
+    /// Whether the effect of the step persists in the target, so a completed
+    /// run can be skipped when resuming. Mounts + unlocking must be redone.
+    pub fn is_resumable(&self) -> bool {
+        !matches!(self, Step::Bind(_) | Step::Mount(_) | Step::OpenEncrypted(_))
     }
 }
 
@@ -174,10 +304,24 @@ mod partitions;
 pub use partitions::{BindMount, FormatPartition, MountPartition, Unmount};
 
 mod packaging;
-pub use packaging::{AddRepo, InstallPackages};
+pub use packaging::{AddRepo, IndexStones, InstallPackages};
+
+mod bootloader;
+pub use bootloader::InstallBootloader;
+
+mod encryption;
+pub use encryption::{CloseEncrypted, EmitCrypttab, EncryptPartition, OpenEncrypted};
+
+mod btrfs;
+pub use btrfs::CreateSubvolumes;
+
+mod swap;
+pub use swap::{ConfigureZram, CreateSwapfile, FormatSwap};
 
 mod cleanup;
 pub use cleanup::Cleanup;
 
 mod postinstall;
-pub use postinstall::{CreateAccount, EmitFstab, FstabEntry, SetLocale, SetMachineID, SetPassword, SetTimezone};
+pub use postinstall::{
+    CreateAccount, EmitFstab, FstabEntry, RecordInstall, SaveLog, SetLocale, SetMachineID, SetPassword, SetTimezone,
+};
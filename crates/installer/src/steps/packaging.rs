@@ -3,9 +3,10 @@
 // SPDX-License-Identifier: MPL-2.0
 
 //! Package management encapsulation (moss only)
-use std::process::Command;
+use std::{collections::BTreeSet, path::PathBuf, process::Command};
 
-use super::Context;
+use super::{Context, Event};
+use crate::index;
 
 /// Add a repository to the target disk
 #[derive(Debug)]
@@ -36,7 +37,47 @@ impl<'a> AddRepo {
         cmd.arg("-y");
 
         // Run
-        context.run_command(&mut cmd)
+        context.run_command_streamed(&mut cmd, &mut |line| context.emit(Event::Log { line: line.into() }))
+    }
+}
+
+/// Index a directory of `.stone` packages so moss can use it as a repository.
+/// The packages are linked into a writable directory first, as the source is
+/// usually on read-only live media.
+#[derive(Debug)]
+pub struct IndexStones {
+    pub(crate) stones: PathBuf,
+    pub(crate) directory: PathBuf,
+}
+
+impl<'a> IndexStones {
+    /// Basic display title
+    pub(super) fn title(&self) -> String {
+        "Index packages".into()
+    }
+
+    /// Render the action
+    pub(super) fn describe(&self) -> String {
+        format!("{} in {}", self.stones.display(), self.directory.display())
+    }
+
+    /// Link the packages and run `moss index`
+    pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), super::Error> {
+        context.create_dir_all(&self.directory)?;
+        for stone in index::stones_in(&self.stones)? {
+            let Some(file_name) = stone.file_name() else {
+                continue;
+            };
+            let link = self.directory.join(file_name);
+            context.remove_file(&link)?;
+            context.symlink(&stone, &link)?;
+        }
+
+        let mut cmd = Command::new("moss");
+        cmd.arg("index");
+        cmd.arg(&self.directory);
+        context.run_command_captured(&mut cmd, None)?;
+        Ok(())
     }
 }
 
@@ -66,6 +107,204 @@ impl<'a> InstallPackages {
         cmd.args(&self.names);
         cmd.arg("-y");
 
-        context.run_command(&mut cmd)
+        let mut progress = MossProgress::default();
+        context.run_command_streamed(&mut cmd, &mut |line| {
+            if let Some(event) = progress.parse(line) {
+                context.emit(event);
+            }
+            context.emit(Event::Log { line: line.into() });
+        })
+    }
+}
+
+/// Share of the progress given to fetching + unpacking, the remainder is blitting
+const FETCH_WEIGHT: f32 = 0.9;
+
+/// What moss is currently doing
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    #[default]
+    Resolve,
+
+    /// Listing the packages that will be installed
+    Listing,
+
+    /// Downloading and unpacking packages into the cache
+    Fetch,
+
+    /// Blitting the new state into the root
+    Blit,
+}
+
+/// Translates the (uncoloured) output of `moss install` into progress
+///
+/// moss is expected to announce the transaction with a header and a columned
+/// package list (TODO: check against output captured from a real run):
+///
+/// ```text
+/// The following package(s) will be installed:
+///
+///   bash-5.2.37-1        coreutils-9.5-2
+///
+/// Installed bash (1.2 MiB)
+/// Installed coreutils (3.4 MiB)
+/// Blitted 12345 files
+/// ```
+///
+/// Every package is then reported as it is fetched and unpacked, possibly
+/// on several lines, followed by blitting. Unrecognised lines are ignored so
+/// a change in format only costs determinate progress, never the installation.
+#[derive(Debug, Default)]
+struct MossProgress {
+    phase: Phase,
+    total: usize,
+
+    /// Packages reported so far
+    seen: BTreeSet<String>,
+    bytes: u64,
+}
+
+impl MossProgress {
+    /// Update from a line of output, returning progress if it changed
+    fn parse(&mut self, line: &str) -> Option<Event> {
+        let line = strip_ansi(line);
+        let line = line.trim();
+
+        if line.contains("will be installed") {
+            self.phase = Phase::Listing;
+            self.total = 0;
+            return self.progress("Resolving packages".into());
+        }
+
+        if let Some(rest) = ["Installed", "Cached", "Fetched", "Unpacked"]
+            .iter()
+            .find_map(|status| line.strip_prefix(status).filter(|r| r.starts_with(' ')))
+        {
+            self.phase = Phase::Fetch;
+            let name = rest.split_whitespace().next().unwrap_or_default();
+            if self.seen.insert(name.to_owned()) {
+                self.bytes += parse_size(rest).unwrap_or_default();
+            }
+            let downloaded = human_bytes::human_bytes(self.bytes as f64);
+            let message = if self.total > 0 {
+                format!("{name} ({}/{}, {downloaded})", self.seen.len(), self.total)
+            } else {
+                format!("{name} ({downloaded})")
+            };
+            return self.progress(message);
+        }
+
+        if line.starts_with("Blit") {
+            self.phase = Phase::Blit;
+            return self.progress("Blitting".into());
+        }
+
+        if self.phase == Phase::Listing && line.is_empty() && self.total > 0 {
+            self.phase = Phase::Fetch;
+        } else if self.phase == Phase::Listing {
+            // Columns are separated by at least two spaces
+            self.total += line.split("  ").filter(|entry| !entry.trim().is_empty()).count();
+        }
+        None
+    }
+
+    fn progress(&self, message: String) -> Option<Event> {
+        let fraction = match self.phase {
+            Phase::Resolve | Phase::Listing => 0.0,
+            Phase::Fetch if self.total > 0 => {
+                FETCH_WEIGHT * (self.seen.len().min(self.total) as f32 / self.total as f32)
+            }
+            // Without a total, only the message is meaningful
+            Phase::Fetch => 0.0,
+            Phase::Blit => FETCH_WEIGHT,
+        };
+        Some(Event::Progress {
+            fraction,
+            message: Some(message),
+        })
+    }
+}
+
+/// Remove ANSI escape sequences (colours) from a line
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end with a letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Parse a human readable size such as `(1.2 MiB)` from the line
+fn parse_size(line: &str) -> Option<u64> {
+    let start = line.rfind('(')?;
+    let end = start + line[start..].find(')')?;
+    let (value, unit) = line[start + 1..end].trim().split_once(' ')?;
+    let value = value.parse::<f64>().ok()?;
+    let multiplier = match unit {
+        "B" => 1.0,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "kB" | "KB" => 1000.0,
+        "MB" => 1000.0 * 1000.0,
+        "GB" => 1000.0 * 1000.0 * 1000.0,
+        _ => return None,
+    };
+    Some((value * multiplier) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_size, Event, MossProgress};
+
+    fn fraction(event: Option<Event>) -> Option<f32> {
+        match event {
+            Some(Event::Progress { fraction, .. }) => Some(fraction),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_moss_progress() {
+        let mut progress = MossProgress::default();
+        let lines = [
+            "The following package(s) will be installed:",
+            "",
+            "  bash-5.2.37-1        coreutils-9.5-2",
+            "  \x1b[1mglibc-2.40-3\x1b[0m    linux-desktop-6.12.1-1",
+            "",
+        ];
+        for line in lines {
+            assert!(progress.parse(line).is_none_or(|e| fraction(Some(e)) == Some(0.0)));
+        }
+        assert_eq!(progress.total, 4);
+
+        assert_eq!(fraction(progress.parse("Fetched bash (1.0 MiB)")), Some(0.225));
+        assert_eq!(fraction(progress.parse("Unpacked bash (1.0 MiB)")), Some(0.225));
+        assert_eq!(fraction(progress.parse("Installed bash (1.0 MiB)")), Some(0.225));
+        assert_eq!(
+            fraction(progress.parse("\x1b[32mInstalled\x1b[0m coreutils (1.0 MiB)")),
+            Some(0.45)
+        );
+        assert_eq!(progress.bytes, 2 * 1024 * 1024);
+        assert!(progress.parse("warning: something unexpected").is_none());
+        assert_eq!(fraction(progress.parse("Blitted 12345 files")), Some(0.9));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("bash (2 KiB)"), Some(2048));
+        assert_eq!(parse_size("bash (1.5 kB)"), Some(1500));
+        assert_eq!(parse_size("bash"), None);
     }
 }
@@ -6,37 +6,29 @@
 
 use std::{path::PathBuf, process::Command};
 
-use fs_err as fs;
-use system::disk::Partition;
+use crate::Filesystem;
 
 use super::Context;
 
 /// Format a partition
 #[derive(Debug)]
-pub struct FormatPartition<'a> {
-    /// What partition are we formatting
-    pub(crate) partition: &'a Partition,
+pub struct FormatPartition {
+    /// What device are we formatting (partition or mapper device)
+    pub(crate) device: PathBuf,
 
     /// What filesystem would you like it to have
-    pub(crate) filesystem: String,
+    pub(crate) filesystem: Filesystem,
+
+    /// Optional filesystem label
+    pub(crate) label: Option<String>,
 }
 
-impl<'a> FormatPartition<'a> {
+impl<'a> FormatPartition {
     pub(super) fn execute(&self, context: &impl Context<'a>) -> Result<(), super::Error> {
-        let fs = self.filesystem.to_lowercase();
-        let (exec, args) = match fs.as_str() {
-            "ext4" => ("mkfs.ext4", ["-F", &self.partition.path.display().to_string()]),
-            "xfs" => ("mkfs.xfs", ["-f", &self.partition.path.display().to_string()]),
-            "f2fs" => ("mkfs.f2fs", ["-f", &self.partition.path.display().to_string()]),
-            _ => unimplemented!(),
-        };
-        log::info!("Formatting {} as {}", self.partition.path.display(), self.filesystem);
-        log::trace!("Running: {exec:?} w/ {args:?}");
-
-        // For now we drop output, but we'll wire up stdout/stderr in context
-        let mut cmd = Command::new(exec);
-        cmd.args(args);
-        let _ = context.run_command_captured(&mut cmd, None)?;
+        log::info!("Formatting {} as {}", self.device.display(), self.filesystem);
+
+        let mut cmd = self.filesystem.mkfs_command(&self.device, self.label.as_deref(), None);
+        context.run_command_captured(&mut cmd, None)?;
         Ok(())
     }
 
@@ -45,36 +37,38 @@ impl<'a> FormatPartition<'a> {
     }
 
     pub(super) fn describe(&self) -> String {
-        format!("{} as {}", self.partition.path.display(), self.filesystem)
+        format!("{} as {}", self.device.display(), self.filesystem)
     }
 }
 
 /// Mount a given partition
 #[derive(Debug)]
-pub struct MountPartition<'a> {
-    /// Which partition?
-    pub(crate) partition: &'a Partition,
+pub struct MountPartition {
+    /// Which device (partition or mapper device)?
+    pub(crate) device: PathBuf,
 
     /// Where are we mounting it?
     pub(crate) mountpoint: PathBuf,
+
+    /// Extra mount options (`-o`)
+    pub(crate) options: Option<String>,
 }
 
-impl<'a> MountPartition<'a> {
+impl<'a> MountPartition {
     pub(super) fn execute(&self, context: &impl Context<'a>) -> Result<(), super::Error> {
-        log::info!(
-            "Mounting {} to {}",
-            self.partition.path.display(),
-            self.mountpoint.display()
-        );
+        log::info!("Mounting {} to {}", self.device.display(), self.mountpoint.display());
 
         // Ensure target exists
-        fs::create_dir_all(&self.mountpoint)?;
-        let source = self.partition.path.to_string_lossy().to_string();
+        context.create_dir_all(&self.mountpoint)?;
+        let source = self.device.to_string_lossy().to_string();
         let dest = self.mountpoint.to_string_lossy().to_string();
         let mut cmd = Command::new("mount");
+        if let Some(options) = self.options.as_ref() {
+            cmd.args(["-o", options]);
+        }
         cmd.args([&source, &dest]);
 
-        let _ = context.run_command_captured(&mut cmd, None)?;
+        context.run_command_captured(&mut cmd, None)?;
         Ok(())
     }
 
@@ -83,7 +77,7 @@ impl<'a> MountPartition<'a> {
     }
 
     pub(super) fn describe(&self) -> String {
-        format!("{} as {}", self.partition.path.display(), self.mountpoint.display())
+        format!("{} as {}", self.device.display(), self.mountpoint.display())
     }
 }
 
@@ -102,13 +96,13 @@ impl<'a> BindMount {
         log::info!("Bind mounting {} to {}", self.source.display(), self.dest.display());
 
         // Ensure target exists
-        fs::create_dir_all(&self.dest)?;
+        context.create_dir_all(&self.dest)?;
         let source = self.source.to_string_lossy().to_string();
         let dest = self.dest.to_string_lossy().to_string();
         let mut cmd = Command::new("mount");
         cmd.args(["--bind", &source, &dest]);
 
-        let _ = context.run_command_captured(&mut cmd, None)?;
+        context.run_command_captured(&mut cmd, None)?;
         Ok(())
     }
 
@@ -143,12 +137,13 @@ impl<'a> Unmount {
         let mut cmd = Command::new("umount");
         cmd.arg(dest);
 
-        let _ = context.run_command_captured(&mut cmd, None)?;
+        context.run_command_captured(&mut cmd, None)?;
         Ok(())
     }
 }
 
 /// A cleanup helper that invokes `sync`
+#[derive(Debug)]
 pub struct SyncFS {}
 
 impl<'a> SyncFS {
@@ -164,7 +159,7 @@ impl<'a> SyncFS {
         log::info!("Syncing filesystems");
 
         let mut cmd = Command::new("sync");
-        let _ = context.run_command_captured(&mut cmd, None);
+        context.run_command_captured(&mut cmd, None)?;
         Ok(())
     }
 }
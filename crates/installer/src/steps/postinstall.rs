@@ -4,20 +4,20 @@
 
 //! Post-installation tasks
 
-use std::{fmt::Display, process::Command};
+use std::{fmt::Display, path::Path, process::Command};
 
 use fs_err as fs;
 use system::locale::Locale;
 
-use crate::{Account, SystemPartition};
+use crate::{Account, Filesystem, Manifest, Password, Subvolume, SystemPartition};
 
-use super::{Context, Error};
+use super::{Context, Error, InstallLog};
 
 /// Configure an account on the system
 #[derive(Debug)]
 pub struct SetPassword<'a> {
     pub(crate) account: &'a Account,
-    pub(crate) password: String,
+    pub(crate) password: Password,
 }
 
 /// Create an account
@@ -41,7 +41,14 @@ impl<'a> SetPassword<'a> {
         cmd.arg(context.root().clone());
         cmd.arg("chpasswd");
 
-        let password_text = format!("{}:{}\n", &self.account.username, self.password);
+        let password = match &self.password {
+            Password::Plain(password) => password,
+            Password::Hashed(hash) => {
+                cmd.arg("-e");
+                hash
+            }
+        };
+        let password_text = format!("{}:{}\n", &self.account.username, password);
         context.run_command_captured(&mut cmd, Some(&password_text))?;
 
         Ok(())
@@ -93,7 +100,7 @@ impl<'a> SetLocale<'a> {
     pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
         let contents = format!("LANG={}\n", self.locale.name);
         let path = context.root().join("etc").join("locale.conf");
-        fs::write(path, &contents)?;
+        context.write_file(&path, &contents)?;
 
         Ok(())
     }
@@ -115,11 +122,9 @@ impl<'a> SetTimezone<'a> {
     }
 
     pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
-        fs::remove_file(context.root().join("etc").join("localtime")).ok();
-        std::os::unix::fs::symlink(
-            format!("../usr/share/zoneinfo/{}", self.timezone),
-            context.root().join("etc").join("localtime"),
-        )?;
+        let link = context.root().join("etc").join("localtime");
+        context.remove_file(&link)?;
+        context.symlink(Path::new(&format!("../usr/share/zoneinfo/{}", self.timezone)), &link)?;
 
         Ok(())
     }
@@ -140,9 +145,7 @@ impl<'a> SetMachineID {
 
     pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
         let file = context.root().join("etc").join("machine-id");
-        if file.exists() {
-            fs::remove_file(file)?;
-        }
+        context.remove_file(&file)?;
 
         let mut cmd = Command::new("chroot");
         cmd.arg(context.root().clone());
@@ -223,27 +226,83 @@ impl Default for EmitFstab {
     }
 }
 
-impl TryFrom<&SystemPartition> for FstabEntry {
-    type Error = Error;
-    fn try_from(value: &SystemPartition) -> Result<Self, Error> {
-        // Honestly, this is a bit ext4 centric, no ssd care given
+impl FstabEntry {
+    /// Create an entry for a partition that is (or will be) formatted with the given filesystem,
+    /// regardless of what superblock was detected at probe time.
+    pub fn with_filesystem(value: &SystemPartition, filesystem: &Filesystem) -> Result<Self, Error> {
+        let mountpoint = value.mountpoint.clone().ok_or(Error::NoMountpoint)?;
         let s = Self::Device {
             // NOTE: This is always PartUUID for us, we only do GPT.
             fs: format!("PARTUUID={}", &value.partition.uuid),
-            mountpoint: value.mountpoint.clone().ok_or(Error::NoMountpoint)?,
-            kind: value
-                .partition
-                .sb
-                .as_ref()
-                .map(|sb| sb.to_string())
-                .ok_or(Error::UnknownFilesystem)?,
-            opts: "rw,errors=remount-ro".to_string(),
+            pass: filesystem.fstab_pass(&mountpoint),
+            mountpoint,
+            kind: filesystem.name().to_string(),
+            opts: filesystem.mount_options().to_string(),
             dump: 0,
-            pass: 1,
         };
 
         Ok(s)
     }
+
+    /// Create an entry for a btrfs subvolume on the given source device
+    pub fn with_subvolume(source: &str, subvolume: &Subvolume) -> Self {
+        let mountpoint = subvolume.mountpoint.display().to_string();
+        Self::Device {
+            fs: source.to_string(),
+            pass: Filesystem::BTRFS.fstab_pass(&mountpoint),
+            mountpoint,
+            kind: Filesystem::BTRFS.name().to_string(),
+            opts: subvolume.mount_options(),
+            dump: 0,
+        }
+    }
+
+    /// Create a swap entry for the given source (device or swapfile)
+    pub fn swap(source: impl Into<String>) -> Self {
+        Self::Device {
+            fs: source.into(),
+            mountpoint: "none".into(),
+            kind: "swap".into(),
+            opts: "defaults".into(),
+            dump: 0,
+            pass: 0,
+        }
+    }
+
+    /// Override the source device (`fs`) of a device entry
+    pub fn with_source(self, source: impl Into<String>) -> Self {
+        match self {
+            Self::Device {
+                mountpoint,
+                kind,
+                opts,
+                dump,
+                pass,
+                ..
+            } => Self::Device {
+                fs: source.into(),
+                mountpoint,
+                kind,
+                opts,
+                dump,
+                pass,
+            },
+            comment => comment,
+        }
+    }
+}
+
+impl TryFrom<&SystemPartition> for FstabEntry {
+    type Error = Error;
+    fn try_from(value: &SystemPartition) -> Result<Self, Error> {
+        let filesystem = value
+            .partition
+            .sb
+            .as_ref()
+            .and_then(|sb| sb.to_string().parse::<Filesystem>().ok())
+            .ok_or(Error::UnknownFilesystem)?;
+        Self::with_filesystem(value, &filesystem)
+    }
 }
 
 impl<'a> EmitFstab {
@@ -266,7 +325,146 @@ impl<'a> EmitFstab {
     pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
         let file = context.root().join("etc").join("fstab");
         let entries = self.entries.iter().map(|e| e.to_string()).collect::<Vec<_>>();
-        fs::write(file, entries.join("\n"))?;
+        context.write_file(&file, &entries.join("\n"))?;
         Ok(())
     }
 }
+
+/// Record a replayable manifest of the installation in the target, readable
+/// by root alone as it carries the password hashes
+#[derive(Debug)]
+pub struct RecordInstall {
+    pub(crate) manifest: Manifest,
+}
+
+impl<'a> RecordInstall {
+    /// Location of the record, relative to the target root
+    pub const PATH: &'static str = "var/log/lichen/install.json";
+
+    pub(super) fn title(&self) -> String {
+        "Record installation".into()
+    }
+
+    pub(super) fn describe(&self) -> String {
+        format!("/{}", Self::PATH)
+    }
+
+    /// Plain text passwords were never recorded, so pick up the hashes
+    /// chpasswd generated from the target's shadow file.
+    pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
+        let shadow = context.read_to_string(&context.root().join("etc").join("shadow"))?;
+        let hash_for = |username: &str| {
+            shadow.lines().find_map(|line| {
+                let mut fields = line.split(':');
+                let (name, hash) = (fields.next()?, fields.next()?);
+                (name == username && hash.starts_with('$')).then(|| hash.to_owned())
+            })
+        };
+
+        let mut manifest = self.manifest.clone();
+        for account in manifest.accounts.iter_mut().filter(|a| a.password_hash.is_none()) {
+            account.password_hash = hash_for(&account.username);
+        }
+
+        let file = context.root().join(Self::PATH);
+        if let Some(parent) = file.parent() {
+            context.create_dir_all(parent)?;
+        }
+        context.write_private_file(&file, &serde_json::to_string_pretty(&manifest)?)?;
+        Ok(())
+    }
+}
+
+/// Copy the install log into the target, readable by root alone as it
+/// records every command line
+#[derive(Debug)]
+pub struct SaveLog {}
+
+impl<'a> SaveLog {
+    pub(super) fn title(&self) -> String {
+        "Save install log".into()
+    }
+
+    pub(super) fn describe(&self) -> String {
+        format!("/{}", InstallLog::TARGET_PATH)
+    }
+
+    pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
+        let Some(install_log) = context.install_log() else {
+            return Ok(());
+        };
+        let dest = context.root().join(InstallLog::TARGET_PATH);
+        if let Some(parent) = dest.parent() {
+            context.create_dir_all(parent)?;
+        }
+        context.write_private_file(&dest, &fs::read_to_string(install_log.path())?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use fs_err as fs;
+
+    use super::{RecordInstall, SaveLog};
+    use crate::{
+        steps::{context::tests::TestContext, InstallLog},
+        Manifest,
+    };
+
+    #[test]
+    fn test_record_install() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::write(root.join("etc/shadow"), "root:$6$salt$hash:19000::::::\n").unwrap();
+        let context = TestContext {
+            install_log: InstallLog::open(root.join("install.log")).unwrap(),
+            root: root.to_path_buf(),
+        };
+        let manifest: Manifest = toml::from_str(
+            r#"
+            filesystem = "ext4"
+            [storage]
+            strategy = "whole-disk"
+            disk = { path = "/dev/sda" }
+            [[accounts]]
+            username = "root"
+            "#,
+        )
+        .unwrap();
+        let record = RecordInstall { manifest };
+        record.execute(&context).unwrap();
+
+        let path = root.join(RecordInstall::PATH);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(fs::read_to_string(&path).unwrap().contains("$6$salt$hash"));
+
+        // Without the shadow file the hashes would silently be lost
+        fs::remove_file(root.join("etc/shadow")).unwrap();
+        assert!(record.execute(&context).is_err());
+    }
+
+    #[test]
+    fn test_save_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let context = TestContext {
+            install_log: InstallLog::open(root.join("install.log")).unwrap(),
+            root: root.to_path_buf(),
+        };
+        context.install_log.line("$ cryptsetup luksFormat /dev/sda3");
+        SaveLog {}.execute(&context).unwrap();
+
+        for path in [context.install_log.path(), &root.join(InstallLog::TARGET_PATH)] {
+            let mode = fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert!(fs::read_to_string(root.join(InstallLog::TARGET_PATH))
+            .unwrap()
+            .contains("luksFormat"));
+    }
+}
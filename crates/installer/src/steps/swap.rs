@@ -0,0 +1,136 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Swap partitions, swapfiles and zram
+
+use std::{path::PathBuf, process::Command};
+
+use crate::Filesystem;
+
+use super::{Context, Error};
+
+const MIB: u64 = 1024 * 1024;
+
+/// Format a partition as swap
+#[derive(Debug)]
+pub struct FormatSwap {
+    pub(crate) device: PathBuf,
+}
+
+impl<'a> FormatSwap {
+    pub(super) fn title(&self) -> String {
+        "Format swap".into()
+    }
+
+    pub(super) fn describe(&self) -> String {
+        self.device.display().to_string()
+    }
+
+    pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
+        log::info!("Formatting {} as swap", self.device.display());
+
+        let mut cmd = Command::new("mkswap");
+        cmd.arg(&self.device);
+        context.run_command_captured(&mut cmd, None)?;
+        Ok(())
+    }
+}
+
+/// Create a swapfile within the root filesystem
+#[derive(Debug)]
+pub struct CreateSwapfile {
+    /// Path of the swapfile, relative to the target root
+    pub(crate) path: PathBuf,
+
+    /// Size in bytes
+    pub(crate) size: u64,
+
+    /// Root filesystem, which dictates how the file must be created
+    pub(crate) filesystem: Filesystem,
+}
+
+impl<'a> CreateSwapfile {
+    pub(super) fn title(&self) -> String {
+        "Create swapfile".into()
+    }
+
+    pub(super) fn describe(&self) -> String {
+        format!("/{} ({} MiB)", self.path.display(), self.size / MIB)
+    }
+
+    pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
+        let file = context.root().join(&self.path);
+        let size_mib = (self.size / MIB).to_string();
+        log::info!("Creating {size_mib} MiB swapfile {}", file.display());
+
+        if self.filesystem == Filesystem::BTRFS {
+            // Swapfiles must live in a subvolume that won't be snapshotted, and
+            // `mkswapfile` takes care of NOCOW + disabling compression.
+            if let Some(parent) = file.parent() {
+                let mut cmd = Command::new("btrfs");
+                cmd.args(["subvolume", "create"]);
+                cmd.arg(parent);
+                context.run_command_captured(&mut cmd, None)?;
+            }
+            let mut cmd = Command::new("btrfs");
+            cmd.args(["filesystem", "mkswapfile", "--size"]);
+            cmd.arg(format!("{size_mib}m"));
+            cmd.arg(&file);
+            context.run_command_captured(&mut cmd, None)?;
+            return Ok(());
+        }
+
+        if let Some(parent) = file.parent() {
+            context.create_dir_all(parent)?;
+        }
+
+        // xfs + f2fs may treat preallocated extents as holes, so write them out fully
+        let mut cmd = if self.filesystem == Filesystem::EXT4 {
+            let mut cmd = Command::new("fallocate");
+            cmd.arg("-l");
+            cmd.arg(format!("{size_mib}M"));
+            cmd.arg(&file);
+            cmd
+        } else {
+            let mut cmd = Command::new("dd");
+            cmd.args(["if=/dev/zero", "bs=1M"]);
+            cmd.arg(format!("count={size_mib}"));
+            cmd.arg(format!("of={}", file.display()));
+            cmd
+        };
+        context.run_command_captured(&mut cmd, None)?;
+
+        let mut cmd = Command::new("chmod");
+        cmd.arg("600");
+        cmd.arg(&file);
+        context.run_command_captured(&mut cmd, None)?;
+
+        let mut cmd = Command::new("mkswap");
+        cmd.arg(&file);
+        context.run_command_captured(&mut cmd, None)?;
+        Ok(())
+    }
+}
+
+/// Write a zram-generator configuration
+#[derive(Debug)]
+pub struct ConfigureZram {}
+
+impl<'a> ConfigureZram {
+    pub(super) fn title(&self) -> String {
+        "Configure zram".into()
+    }
+
+    pub(super) fn describe(&self) -> String {
+        "via zram-generator".into()
+    }
+
+    pub(super) fn execute(&self, context: &'a impl Context<'a>) -> Result<(), Error> {
+        let dir = context.root().join("etc").join("systemd");
+        context.create_dir_all(&dir)?;
+        let contents = "[zram0]\nzram-size = min(ram / 2, 8192)\ncompression-algorithm = zstd\n";
+        context.write_file(&dir.join("zram-generator.conf"), contents)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Partitioning strategies
+//!
+//! Rather than requiring the user to prepare partitions ahead of time,
+//! a strategy will lay out a disk for us and hand back the resulting
+//! [`BootPartition`] and [`SystemPartition`] for use in the [`crate::Model`]
+
+use std::{io, path::PathBuf, process::Command};
+
+use system::disk::{self, Disk, Layout, Partition, PartitionKind, Table};
+use thiserror::Error;
+
+use crate::{plan::placeholder_uuid, steps, steps::Context, BootPartition, SystemPartition};
+
+/// Sizes of the sysfs `size` attribute are always in 512-byte sectors
+const SECTOR_SIZE: u64 = 512;
+
+const MIB: u64 = 1024 * 1024;
+const GIB: u64 = 1024 * MIB;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    IO(#[from] io::Error),
+
+    #[error("disk: {0}")]
+    Disk(#[from] disk::Error),
+
+    #[error(transparent)]
+    Step(#[from] steps::Error),
+
+    #[error("disk is too small: {size} bytes available, {needed} bytes needed")]
+    DiskTooSmall { size: u64, needed: u64 },
+
+    #[error("missing partition after partitioning: {0}")]
+    MissingPartition(&'static str),
+}
+
+/// Use an entire disk for the installation, replacing any existing
+/// partition table with a fresh GPT layout of ESP, XBOOTLDR and root.
+#[derive(Debug)]
+pub struct WholeDisk<'a> {
+    disk: &'a Disk,
+    esp_size: u64,
+    xbootldr_size: u64,
+    min_root_size: u64,
+}
+
+impl<'a> WholeDisk<'a> {
+    /// Create a new strategy for the given disk with default sizes
+    pub fn new(disk: &'a Disk) -> Self {
+        Self {
+            disk,
+            esp_size: 512 * MIB,
+            xbootldr_size: 4 * GIB,
+            min_root_size: 20 * GIB,
+        }
+    }
+
+    /// Render the disk for display, i.e. in a plan
+    pub fn describe(&self) -> String {
+        format!("{}, {}", self.disk.path.display(), self.disk)
+    }
+
+    /// Total number of bytes required on the disk
+    pub fn required_size(&self) -> u64 {
+        // Leave a little room for alignment and the GPT headers
+        self.esp_size + self.xbootldr_size + self.min_root_size + 2 * MIB
+    }
+
+    /// Ensure the disk is large enough for this layout
+    pub fn validate(&self) -> Result<(), Error> {
+        let size = self.disk.size * SECTOR_SIZE;
+        let needed = self.required_size();
+        if size < needed {
+            return Err(Error::DiskTooSmall { size, needed });
+        }
+        Ok(())
+    }
+
+    /// Build our layout in a fresh partition table
+    fn build(&self, table: &mut Table) -> Result<(), disk::Error> {
+        table.add("EFI System Partition", PartitionKind::ESP, self.esp_size)?;
+        table.add("Linux extended boot", PartitionKind::XBOOTLDR, self.xbootldr_size)?;
+        table.add_remaining("Serpent OS root", PartitionKind::Root)?;
+        Ok(())
+    }
+
+    /// Report the layout that [`WholeDisk::apply`] would write, without
+    /// touching the disk.
+    pub fn plan(&self) -> Result<Layout, Error> {
+        self.validate()?;
+        let mut table = self.disk.new_table(true)?;
+        self.build(&mut table)?;
+        Ok(table.commit()?)
+    }
+
+    /// The partitions [`WholeDisk::apply`] would produce, without touching
+    /// the disk. PARTUUIDs are placeholders, as they're only generated when applied.
+    pub fn preview(&self) -> Result<(BootPartition, SystemPartition), Error> {
+        let layout = self.plan()?;
+        let parts = layout
+            .entries
+            .iter()
+            .map(|entry| Partition {
+                path: self.partition_device(entry.number),
+                kind: entry.kind,
+                size: entry.size,
+                uuid: placeholder_uuid(entry.number),
+                sb: None,
+            })
+            .collect::<Vec<_>>();
+        self.partitions_from(&parts)
+    }
+
+    /// Device node of the given partition number, i.e. `/dev/nvme0n1p2`
+    fn partition_device(&self, number: u32) -> PathBuf {
+        let disk = self.disk.path.to_string_lossy();
+        if disk.ends_with(|c: char| c.is_ascii_digit()) {
+            format!("{disk}p{number}").into()
+        } else {
+            format!("{disk}{number}").into()
+        }
+    }
+
+    /// Wipe the disk and write the new partition table through the context,
+    /// returning the new layout. A [`crate::plan::DryRun`] context only records
+    /// what would be done.
+    pub fn prepare<'c>(&self, context: &'c impl Context<'c>) -> Result<Layout, Error> {
+        self.validate()?;
+
+        // Old partition tables, filesystems and LUKS headers must not be found again
+        context.run_command_captured(Command::new("wipefs").arg("--all").arg(&self.disk.path), None)?;
+        let layout = context.write_partition_table(self.disk, &|table| self.build(table))?;
+        log::trace!("New layout:\n{layout}");
+
+        // Wait for the kernel + udev to populate /dev/disk/by-partuuid
+        context.run_command_captured(Command::new("udevadm").arg("settle"), None)?;
+
+        // New partitions may start where old filesystems did
+        for entry in layout.entries.iter() {
+            context.run_command_captured(
+                Command::new("wipefs")
+                    .arg("--all")
+                    .arg(self.partition_device(entry.number)),
+                None,
+            )?;
+        }
+        Ok(layout)
+    }
+
+    /// Write the new partition table to disk and return the partitions for
+    /// use in the model.
+    ///
+    /// This is destructive: all existing data on the disk is lost.
+    pub fn apply<'c>(&self, context: &'c impl Context<'c>) -> Result<(BootPartition, SystemPartition), Error> {
+        log::info!("Partitioning {} for whole-disk installation", self.disk.path.display());
+        self.prepare(context)?;
+        self.partitions_from(&self.disk.partitions()?)
+    }
+
+    /// Pick our partitions out of the new layout
+    fn partitions_from(&self, parts: &[Partition]) -> Result<(BootPartition, SystemPartition), Error> {
+        let find = |kind: PartitionKind| parts.iter().find(|p| p.kind == kind).cloned();
+        let esp = find(PartitionKind::ESP).ok_or(Error::MissingPartition("ESP"))?;
+        let xbootldr = find(PartitionKind::XBOOTLDR).ok_or(Error::MissingPartition("XBOOTLDR"))?;
+        let root = find(PartitionKind::Root).ok_or(Error::MissingPartition("/"))?;
+
+        let parent_desc = self.disk.to_string();
+        Ok((
+            BootPartition {
+                esp,
+                xbootldr: Some(xbootldr),
+                parent_desc: parent_desc.clone(),
+                // Fresh boot partitions must be FAT32 for the firmware + bootloader
+                format: true,
+            },
+            SystemPartition {
+                partition: root,
+                mountpoint: Some("/".into()),
+                parent_desc,
+                filesystem: None,
+            },
+        ))
+    }
+}
@@ -0,0 +1,31 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Swap configuration for the installed system
+
+use crate::SystemPartition;
+
+/// How the installed system gets its swap
+#[derive(Debug, Clone)]
+pub enum Swap {
+    /// A dedicated partition, formatted with `mkswap`
+    Partition(Box<SystemPartition>),
+
+    /// A swapfile of the given size (in bytes) on the root filesystem
+    File { size: u64 },
+
+    /// Compressed swap in RAM via zram-generator, for low-disk machines
+    Zram,
+}
+
+impl Swap {
+    /// Human readable description of the swap choice
+    pub fn describe(&self) -> String {
+        match self {
+            Swap::Partition(partition) => format!("partition {partition}"),
+            Swap::File { size } => format!("{} swapfile", human_bytes::human_bytes(*size as f64)),
+            Swap::Zram => "zram".into(),
+        }
+    }
+}
@@ -10,9 +10,9 @@ use std::{
 };
 
 use fs_err as fs;
-use gpt::GptConfig;
+use gpt::{disk::LogicalBlockSize, GptConfig};
 
-use super::{Error, Partition};
+use super::{Error, Partition, Table};
 
 /// Indicates type of disk device
 #[derive(Debug)]
@@ -41,6 +41,7 @@ pub struct Disk {
     pub kind: Kind,
     pub model: Option<String>,
     pub vendor: Option<String>,
+    pub serial: Option<String>,
     pub block_size: u64,
     pub size: u64,
 }
@@ -98,6 +99,7 @@ impl Disk {
         let model = fs::read_to_string(device_link.join("model"))
             .ok()
             .map(|f| f.trim().to_string());
+        let serial = read_serial(path);
         let block_size = str::parse::<u64>(fs::read_to_string(path.join("queue").join("physical_block_size"))?.trim())?;
         let size = str::parse::<u64>(fs::read_to_string(path.join("size"))?.trim())?;
 
@@ -108,6 +110,7 @@ impl Disk {
             kind,
             vendor,
             model,
+            serial,
             block_size,
             size,
         })
@@ -137,4 +140,48 @@ impl Disk {
         }
         Ok(parts)
     }
+
+    /// Logical block size of the device, as used for LBA addressing
+    fn logical_block_size(&self) -> Result<LogicalBlockSize, Error> {
+        // Images have no queue in sysfs
+        if fs::metadata(&self.path)?.is_file() {
+            return Ok(LogicalBlockSize::Lb512);
+        }
+        let file_name = self.path.file_name().ok_or(Error::InvalidDisk)?;
+        let sysfs = PathBuf::from("/sys/class/block")
+            .join(file_name)
+            .join("queue")
+            .join("logical_block_size");
+        let size = str::parse::<u64>(fs::read_to_string(sysfs)?.trim())?;
+        LogicalBlockSize::try_from(size).map_err(|_| Error::InvalidDisk)
+    }
+
+    /// Open the existing GPT on the disk for editing
+    pub fn table(&self, dry_run: bool) -> Result<Table, Error> {
+        Table::open_with(&self.path, self.logical_block_size()?, dry_run)
+    }
+
+    /// Start a new, empty GPT label for the disk, replacing the existing
+    /// partition table when committed.
+    pub fn new_table(&self, dry_run: bool) -> Result<Table, Error> {
+        Table::create_with(&self.path, self.logical_block_size()?, dry_run)
+    }
+}
+
+/// Find the serial number of a disk via sysfs (nvme, virtio) or the udev database (SATA, USB)
+fn read_serial(path: &Path) -> Option<String> {
+    let sysfs = [path.join("device").join("serial"), path.join("serial")]
+        .iter()
+        .filter_map(|p| fs::read_to_string(p).ok())
+        .map(|s| s.trim().to_string())
+        .find(|s| !s.is_empty());
+    if sysfs.is_some() {
+        return sysfs;
+    }
+
+    let dev = fs::read_to_string(path.join("dev")).ok()?;
+    let db = fs::read_to_string(format!("/run/udev/data/b{}", dev.trim())).ok()?;
+    db.lines()
+        .find_map(|l| l.strip_prefix("E:ID_SERIAL_SHORT="))
+        .map(|s| s.to_string())
 }
@@ -25,6 +25,12 @@ pub enum Error {
 
     #[error("superblock: {0}")]
     Superblock(#[from] superblock::Error),
+
+    #[error("unknown partition: {0}")]
+    UnknownPartition(u32),
+
+    #[error("not enough free space")]
+    NoSpace,
 }
 
 mod disks;
@@ -33,6 +39,8 @@ mod partition;
 pub use disks::Kind as DiskKind;
 pub use partition::Kind as PartitionKind;
 pub use partition::Partition;
+pub use partition::SuperblockKind;
+mod table;
+pub use table::{Attributes as PartitionAttributes, Entry as LayoutEntry, Layout, Table};
 
-pub use superblock::Kind as SuperblockKind;
 pub use superblock::Superblock;
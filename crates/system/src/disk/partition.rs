@@ -19,7 +19,7 @@ pub struct Partition {
     pub kind: Kind,
     pub size: u64,
     pub uuid: String,
-    pub sb: Option<super::SuperblockKind>,
+    pub sb: Option<SuperblockKind>,
 }
 
 impl Display for Partition {
@@ -28,23 +28,110 @@ impl Display for Partition {
     }
 }
 /// Specialised type of partition
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum Kind {
     ESP,
     XBOOTLDR,
+
+    /// Discoverable x86-64 root, found by systemd-gpt-auto-generator
+    Root,
     #[default]
     Regular,
 }
 
+impl Kind {
+    /// The GPT partition type GUID used when creating this kind of partition
+    pub fn partition_type(&self) -> partition_types::Type {
+        match self {
+            Kind::ESP => partition_types::EFI,
+            Kind::XBOOTLDR => partition_types::FREEDESK_BOOT,
+            Kind::Root => partition_types::LINUX_ROOT_X64,
+            Kind::Regular => partition_types::LINUX_FS,
+        }
+    }
+}
+
+impl From<&partition_types::Type> for Kind {
+    fn from(value: &partition_types::Type) -> Self {
+        match *value {
+            partition_types::EFI => Kind::ESP,
+            partition_types::FREEDESK_BOOT => Kind::XBOOTLDR,
+            partition_types::LINUX_ROOT_X64 => Kind::Root,
+            _ => Kind::Regular,
+        }
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            Kind::ESP => f.write_str("esp"),
+            Kind::XBOOTLDR => f.write_str("xbootldr"),
+            Kind::Root => f.write_str("root"),
+            Kind::Regular => f.write_str("regular"),
+        }
+    }
+}
+
+/// Filesystem (or container) detected on a partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum SuperblockKind {
+    Btrfs,
+    Ext4,
+    F2FS,
+    LUKS2,
+    XFS,
+    FAT,
+}
+
+impl From<superblock::Kind> for SuperblockKind {
+    fn from(value: superblock::Kind) -> Self {
+        match value {
+            superblock::Kind::Btrfs => SuperblockKind::Btrfs,
+            superblock::Kind::Ext4 => SuperblockKind::Ext4,
+            superblock::Kind::F2FS => SuperblockKind::F2FS,
+            superblock::Kind::LUKS2 => SuperblockKind::LUKS2,
+            superblock::Kind::XFS => SuperblockKind::XFS,
+        }
+    }
+}
+
+impl Display for SuperblockKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            SuperblockKind::Btrfs => f.write_str("btrfs"),
+            SuperblockKind::Ext4 => f.write_str("ext4"),
+            SuperblockKind::F2FS => f.write_str("f2fs"),
+            SuperblockKind::LUKS2 => f.write_str("luks2"),
+            SuperblockKind::XFS => f.write_str("xfs"),
+            SuperblockKind::FAT => f.write_str("vfat"),
+        }
+    }
+}
+
 /// Superblock scanning, self contained
-fn scan_superblock(path: &PathBuf) -> Result<superblock::Kind, super::Error> {
+fn scan_superblock(path: &PathBuf) -> Result<SuperblockKind, super::Error> {
     let fi = fs::File::open(path)?;
     let mut buffer: Vec<u8> = Vec::with_capacity(2 * 1024 * 1024);
     fi.take(2 * 1024 * 1024).read_to_end(&mut buffer)?;
     let mut cursor = Cursor::new(&buffer);
-    let sb = superblock::for_reader(&mut cursor)?;
-    Ok(sb.kind())
+    match superblock::for_reader(&mut cursor) {
+        Ok(sb) => Ok(sb.kind().into()),
+        // superblock doesn't know about FAT, which we need for the ESP
+        Err(_) if is_fat(&buffer) => Ok(SuperblockKind::FAT),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Check for a FAT12/16/32 boot sector by its signature and filesystem type string
+fn is_fat(buffer: &[u8]) -> bool {
+    if buffer.len() < 512 || buffer[510..512] != [0x55, 0xAA] {
+        return false;
+    }
+    // FAT32 keeps the type at offset 82, FAT12/16 at offset 54
+    buffer[82..85] == *b"FAT" || buffer[54..57] == *b"FAT"
 }
 
 impl Partition {
@@ -52,11 +139,7 @@ impl Partition {
     pub fn from(value: &gpt::partition::Partition, block_size: &LogicalBlockSize) -> Result<Self, super::Error> {
         let uuid = value.part_guid.hyphenated().to_string();
         let path = fs::canonicalize(format!("/dev/disk/by-partuuid/{}", uuid))?;
-        let kind = match value.part_type_guid {
-            partition_types::EFI => Kind::ESP,
-            partition_types::FREEDESK_BOOT => Kind::XBOOTLDR,
-            _ => Kind::Regular,
-        };
+        let kind = Kind::from(&value.part_type_guid);
         let sb = scan_superblock(&path).ok();
         let size = value.bytes_len(*block_size)?;
         Ok(Self {
@@ -68,3 +151,35 @@ impl Partition {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use gpt::partition_types;
+
+    use super::{is_fat, Kind};
+
+    fn boot_sector(offset: usize, kind: &[u8]) -> Vec<u8> {
+        let mut buffer = vec![0u8; 512];
+        buffer[offset..offset + kind.len()].copy_from_slice(kind);
+        buffer[510] = 0x55;
+        buffer[511] = 0xAA;
+        buffer
+    }
+
+    #[test]
+    fn test_fat_detection() {
+        assert!(is_fat(&boot_sector(82, b"FAT32   ")));
+        assert!(is_fat(&boot_sector(54, b"FAT16   ")));
+        assert!(!is_fat(&boot_sector(82, b"EXT4    ")));
+        assert!(!is_fat(&[0u8; 512]));
+        assert!(!is_fat(&[0u8; 16]));
+    }
+
+    #[test]
+    fn test_kind_round_trip() {
+        for kind in [Kind::ESP, Kind::XBOOTLDR, Kind::Root, Kind::Regular] {
+            assert_eq!(Kind::from(&kind.partition_type()), kind);
+        }
+        assert_eq!(Kind::Root.partition_type(), partition_types::LINUX_ROOT_X64);
+    }
+}
@@ -0,0 +1,423 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Writable GPT partition tables
+//!
+//! A [`Table`] wraps either a block device or an image file, and allows
+//! editing the GPT in memory before committing the changes to disk. In
+//! dry-run mode the device is never opened for writing, and [`Table::commit`]
+//! simply reports the resulting [`Layout`].
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{self, Seek, SeekFrom},
+    os::{fd::AsRawFd, unix::fs::FileTypeExt},
+    path::{Path, PathBuf},
+};
+
+use bitflags::bitflags;
+use fs_err as fs;
+use gpt::{disk::LogicalBlockSize, mbr::ProtectiveMBR, GptConfig, GptDisk};
+
+use super::{Error, PartitionKind};
+
+/// New partitions are aligned to 1MiB boundaries
+const ALIGNMENT: u64 = 1024 * 1024;
+
+// BLKRRPART: ask the kernel to re-read the partition table
+nix::ioctl_none!(reread_partition_table, 0x12, 95);
+
+bitflags! {
+    /// Attribute bits of a GPT partition entry
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Attributes: u64 {
+        /// Required for the platform to function
+        const REQUIRED = 1 << 0;
+
+        /// Firmware must not produce a block IO protocol for it
+        const NO_BLOCK_IO = 1 << 1;
+
+        /// Legacy BIOS bootable
+        const LEGACY_BIOS_BOOTABLE = 1 << 2;
+
+        /// Grow the filesystem to fill the partition on first mount
+        const GROWFS = 1 << 59;
+
+        /// Mount read-only (discoverable partitions)
+        const READ_ONLY = 1 << 60;
+
+        /// Hide from automatic discovery
+        const HIDDEN = 1 << 62;
+
+        /// Do not automount (discoverable partitions)
+        const NO_AUTO = 1 << 63;
+    }
+}
+
+/// A single partition entry within a [`Layout`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Partition number (1-indexed)
+    pub number: u32,
+
+    /// GPT partition name
+    pub name: String,
+
+    /// Partition type
+    pub kind: PartitionKind,
+
+    /// Unique partition GUID (PARTUUID)
+    pub uuid: String,
+
+    /// First LBA (inclusive)
+    pub first_lba: u64,
+
+    /// Last LBA (inclusive)
+    pub last_lba: u64,
+
+    /// Size in bytes
+    pub size: u64,
+
+    /// Attribute bits
+    pub attributes: Attributes,
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{}: {} ({}, {:.1} MiB, LBA {}-{})",
+            self.number,
+            self.name,
+            self.kind,
+            self.size as f64 / ALIGNMENT as f64,
+            self.first_lba,
+            self.last_lba
+        ))
+    }
+}
+
+/// The (resulting) layout of a partition table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// Logical block size in bytes
+    pub block_size: u64,
+
+    /// All partitions in the table
+    pub entries: Vec<Entry>,
+}
+
+impl Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let entries = self.entries.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        f.write_str(&entries.join("\n"))
+    }
+}
+
+/// An editable GPT partition table on a block device or image file
+pub struct Table {
+    path: PathBuf,
+    gpt: GptDisk<Box<fs::File>>,
+
+    /// Protective MBR to write when this is a new label
+    mbr: Option<ProtectiveMBR>,
+
+    /// Never write anything
+    dry_run: bool,
+}
+
+impl Table {
+    /// Open the existing GPT label on the given device or image
+    pub fn open(path: impl AsRef<Path>, dry_run: bool) -> Result<Self, Error> {
+        Self::open_with(path, LogicalBlockSize::Lb512, dry_run)
+    }
+
+    /// Create a new, empty GPT label on the given device or image, discarding
+    /// any existing partitions once committed.
+    pub fn create(path: impl AsRef<Path>, dry_run: bool) -> Result<Self, Error> {
+        Self::create_with(path, LogicalBlockSize::Lb512, dry_run)
+    }
+
+    /// Open the existing GPT label with an explicit logical block size
+    pub fn open_with(path: impl AsRef<Path>, block_size: LogicalBlockSize, dry_run: bool) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let device = Box::new(Self::open_device(&path, dry_run)?);
+        let gpt = GptConfig::default()
+            .writable(!dry_run)
+            .logical_block_size(block_size)
+            .open_from_device(device)?;
+        Ok(Self {
+            path,
+            gpt,
+            mbr: None,
+            dry_run,
+        })
+    }
+
+    /// Create a new GPT label with an explicit logical block size
+    pub fn create_with(path: impl AsRef<Path>, block_size: LogicalBlockSize, dry_run: bool) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut device = Box::new(Self::open_device(&path, dry_run)?);
+
+        // Block devices report a zero length in their metadata, so seek instead
+        let total_bytes = device.seek(SeekFrom::End(0))?;
+        device.seek(SeekFrom::Start(0))?;
+        let sectors = total_bytes / u64::from(block_size);
+        let mbr = ProtectiveMBR::with_lb_size(u32::try_from(sectors.saturating_sub(1)).unwrap_or(u32::MAX));
+
+        let mut gpt = GptConfig::default()
+            .writable(!dry_run)
+            .logical_block_size(block_size)
+            .create_from_device(device, None)?;
+        // Initialise the headers with a blank partition table
+        gpt.update_partitions(BTreeMap::new())?;
+
+        Ok(Self {
+            path,
+            gpt,
+            mbr: Some(mbr),
+            dry_run,
+        })
+    }
+
+    fn open_device(path: &Path, dry_run: bool) -> Result<fs::File, Error> {
+        let file = fs::OpenOptions::new().read(true).write(!dry_run).open(path)?;
+        Ok(file)
+    }
+
+    /// Path of the underlying device or image
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether this table is in dry-run mode
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    fn block_size(&self) -> u64 {
+        u64::from(*self.gpt.logical_block_size())
+    }
+
+    /// Add a partition of the given size (in bytes, rounded up to 1MiB alignment)
+    /// into the first suitable free region, returning its partition number.
+    pub fn add(&mut self, name: &str, kind: PartitionKind, size: u64) -> Result<u32, Error> {
+        let size = size.max(1).div_ceil(ALIGNMENT) * ALIGNMENT;
+        let alignment = ALIGNMENT / self.block_size();
+        let number = self
+            .gpt
+            .add_partition(name, size, kind.partition_type(), 0, Some(alignment))?;
+        Ok(number)
+    }
+
+    /// Add a partition filling the largest free region, returning its partition number.
+    pub fn add_remaining(&mut self, name: &str, kind: PartitionKind) -> Result<u32, Error> {
+        let alignment = ALIGNMENT / self.block_size();
+        let (start, length) = self
+            .gpt
+            .find_free_sectors()
+            .into_iter()
+            .max_by_key(|(_, length)| *length)
+            .ok_or(Error::NoSpace)?;
+
+        let end = start + length;
+        let first_lba = start.div_ceil(alignment) * alignment;
+        let length = end.saturating_sub(first_lba) / alignment * alignment;
+        if length == 0 {
+            return Err(Error::NoSpace);
+        }
+
+        let number = self.gpt.find_next_partition_id().ok_or(Error::NoSpace)?;
+        self.gpt
+            .add_partition_at(name, number, first_lba, length, kind.partition_type(), 0)?;
+        Ok(number)
+    }
+
+    /// Remove the given partition
+    pub fn delete(&mut self, number: u32) -> Result<(), Error> {
+        let mut partitions = self.gpt.partitions().clone();
+        partitions.remove(&number).ok_or(Error::UnknownPartition(number))?;
+        self.gpt.update_partitions(partitions)?;
+        Ok(())
+    }
+
+    /// Resize the given partition to `size` bytes (rounded up to 1MiB alignment),
+    /// keeping its start position. Growing is limited to the free space directly
+    /// following the partition.
+    pub fn resize(&mut self, number: u32, size: u64) -> Result<(), Error> {
+        let block_size = self.block_size();
+        let size = size.max(1).div_ceil(ALIGNMENT) * ALIGNMENT;
+        let free = self.gpt.find_free_sectors();
+
+        self.update(number, |part| {
+            let last_lba = part.first_lba + (size / block_size) - 1;
+            let limit = free
+                .iter()
+                .find(|(start, _)| *start == part.last_lba + 1)
+                .map(|(start, length)| start + length - 1)
+                .unwrap_or(part.last_lba);
+            if last_lba > limit {
+                return Err(Error::NoSpace);
+            }
+            part.last_lba = last_lba;
+            Ok(())
+        })
+    }
+
+    /// Set the GPT name of the given partition
+    pub fn set_name(&mut self, number: u32, name: &str) -> Result<(), Error> {
+        self.update(number, |part| {
+            part.name = name.to_string();
+            Ok(())
+        })
+    }
+
+    /// Replace the attribute bits of the given partition
+    pub fn set_attributes(&mut self, number: u32, attributes: Attributes) -> Result<(), Error> {
+        self.update(number, |part| {
+            part.flags = attributes.bits();
+            Ok(())
+        })
+    }
+
+    /// Apply a mutation to a single partition entry
+    fn update(
+        &mut self,
+        number: u32,
+        f: impl FnOnce(&mut gpt::partition::Partition) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut partitions = self.gpt.partitions().clone();
+        let part = partitions.get_mut(&number).ok_or(Error::UnknownPartition(number))?;
+        f(part)?;
+        self.gpt.update_partitions(partitions)?;
+        Ok(())
+    }
+
+    /// Report the current (in-memory) layout
+    pub fn layout(&self) -> Layout {
+        let block_size = self.block_size();
+        let entries = self
+            .gpt
+            .partitions()
+            .iter()
+            .map(|(number, part)| Entry {
+                number: *number,
+                name: part.name.clone(),
+                kind: PartitionKind::from(&part.part_type_guid),
+                uuid: part.part_guid.hyphenated().to_string(),
+                first_lba: part.first_lba,
+                last_lba: part.last_lba,
+                size: (part.last_lba - part.first_lba + 1) * block_size,
+                attributes: Attributes::from_bits_retain(part.flags),
+            })
+            .collect::<Vec<_>>();
+        Layout { block_size, entries }
+    }
+
+    /// Write the table to disk and ask the kernel to re-read it when
+    /// the target is a block device. In dry-run mode nothing is written.
+    pub fn commit(self) -> Result<Layout, Error> {
+        let layout = self.layout();
+        if self.dry_run {
+            return Ok(layout);
+        }
+
+        let mut device = self.gpt.write()?;
+        if let Some(mbr) = self.mbr.as_ref() {
+            mbr.overwrite_lba0(&mut device)
+                .map_err(|e| io::Error::other(format!("protective MBR: {e}")))?;
+        }
+        device.sync_all()?;
+
+        if device.metadata()?.file_type().is_block_device() {
+            // SAFETY: fd is valid for the lifetime of `device`, and BLKRRPART takes no argument
+            unsafe { reread_partition_table(device.file().as_raw_fd()) }.map_err(io::Error::from)?;
+        }
+
+        Ok(layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+
+    use super::{Attributes, Table};
+    use crate::disk::PartitionKind;
+
+    const MIB: u64 = 1024 * 1024;
+
+    /// Create a sparse image file to stand in for a loop device, removed on drop
+    fn image(size: u64) -> NamedTempFile {
+        let image = NamedTempFile::new().expect("Failed to create image");
+        image.as_file().set_len(size).expect("Failed to size image");
+        image
+    }
+
+    #[test]
+    fn test_create() {
+        let image = image(256 * MIB);
+        let path = image.path();
+
+        let mut table = Table::create(path, false).expect("Failed to create label");
+        let esp = table.add("ESP", PartitionKind::ESP, 32 * MIB).unwrap();
+        let root = table.add_remaining("root", PartitionKind::Regular).unwrap();
+        let written = table.commit().expect("Failed to commit");
+
+        let layout = Table::open(path, true).expect("Failed to reopen").layout();
+
+        assert_eq!(written, layout);
+        assert_eq!(layout.entries.len(), 2);
+        assert_eq!(layout.entries[0].number, esp);
+        assert_eq!(layout.entries[0].kind, PartitionKind::ESP);
+        assert_eq!(layout.entries[0].size, 32 * MIB);
+        assert_eq!(layout.entries[0].first_lba % (MIB / 512), 0);
+        assert_eq!(layout.entries[1].number, root);
+        assert_eq!(layout.entries[1].kind, PartitionKind::Regular);
+        assert!(layout.entries[1].first_lba > layout.entries[0].last_lba);
+        assert!(layout.entries[1].size > 200 * MIB);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let image = image(64 * MIB);
+        let path = image.path();
+
+        let mut table = Table::create(path, true).expect("Failed to create label");
+        table.add("ESP", PartitionKind::ESP, 16 * MIB).unwrap();
+        let layout = table.commit().expect("Failed to plan");
+        let reopened = Table::open(path, true);
+
+        assert_eq!(layout.entries.len(), 1);
+        assert!(reopened.is_err(), "dry run must not write a label");
+    }
+
+    #[test]
+    fn test_edit() {
+        let image = image(128 * MIB);
+        let path = image.path();
+
+        let mut table = Table::create(path, false).unwrap();
+        let a = table.add("a", PartitionKind::Regular, 16 * MIB).unwrap();
+        let b = table.add("b", PartitionKind::Regular, 16 * MIB).unwrap();
+        table.commit().unwrap();
+
+        let mut table = Table::open(path, false).unwrap();
+        table.delete(a).unwrap();
+        table.resize(b, 32 * MIB).unwrap();
+        table.set_name(b, "renamed").unwrap();
+        table.set_attributes(b, Attributes::NO_AUTO).unwrap();
+        assert!(table.delete(a).is_err());
+        assert!(table.resize(b, 1024 * MIB).is_err());
+        table.commit().unwrap();
+
+        let layout = Table::open(path, true).unwrap().layout();
+
+        assert_eq!(layout.entries.len(), 1);
+        assert_eq!(layout.entries[0].number, b);
+        assert_eq!(layout.entries[0].name, "renamed");
+        assert_eq!(layout.entries[0].size, 32 * MIB);
+        assert_eq!(layout.entries[0].attributes, Attributes::NO_AUTO);
+    }
+}
@@ -0,0 +1,316 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Hardware probing, to pick firmware, microcode and drivers
+//!
+//! Everything is read from `/proc/cpuinfo` and sysfs. Missing entries (as in
+//! containers or some virtual machines) simply mean the hardware is absent.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+/// Well known PCI vendors
+const VENDORS: &[(u16, &str)] = &[(0x1002, "AMD"), (0x10de, "NVIDIA"), (0x8086, "Intel")];
+
+/// PCI base class of display controllers
+const CLASS_DISPLAY: u8 = 0x03;
+
+/// A device on the PCI bus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PciDevice {
+    pub vendor: u16,
+    pub device: u16,
+
+    /// Base class, subclass and programming interface, i.e. `0x030000` for VGA
+    pub class: u32,
+}
+
+impl PciDevice {
+    /// Read the device at the given sysfs path
+    pub fn from_sysfs_path(path: impl AsRef<Path>) -> Option<Self> {
+        let read = |name: &str| {
+            let value = fs::read_to_string(path.as_ref().join(name)).ok()?;
+            u32::from_str_radix(value.trim().trim_start_matches("0x"), 16).ok()
+        };
+        Some(Self {
+            vendor: read("vendor")?.try_into().ok()?,
+            device: read("device")?.try_into().ok()?,
+            class: read("class")?,
+        })
+    }
+
+    /// The PCI base class
+    pub fn base_class(&self) -> u8 {
+        (self.class >> 16) as u8
+    }
+
+    /// Name of the vendor, if well known
+    pub fn vendor_name(&self) -> Option<&'static str> {
+        VENDORS.iter().find(|(id, _)| *id == self.vendor).map(|(_, name)| *name)
+    }
+}
+
+impl Display for PciDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vendor, self.device)
+    }
+}
+
+/// The hardware of this machine, as relevant to package selection
+#[derive(Debug, Default, Clone)]
+pub struct Hardware {
+    /// i.e. `GenuineIntel` or `AuthenticAMD`
+    pub cpu_vendor: Option<String>,
+    pub pci: Vec<PciDevice>,
+    pub wifi: bool,
+    pub bluetooth: bool,
+    pub battery: bool,
+}
+
+impl Hardware {
+    /// Probe the running system
+    pub fn probe() -> Result<Self, Error> {
+        Self::probe_from("/")
+    }
+
+    /// Probe a system whose `/proc` and `/sys` are found within `root`
+    pub fn probe_from(root: impl AsRef<Path>) -> Result<Self, Error> {
+        let root = root.as_ref();
+        let cpuinfo = root.join("proc/cpuinfo");
+        let cpu_vendor = if cpuinfo.exists() {
+            cpu_vendor(&fs::read_to_string(cpuinfo)?)
+        } else {
+            None
+        };
+
+        let pci = entries(root.join("sys/bus/pci/devices"))?
+            .iter()
+            .filter_map(PciDevice::from_sysfs_path)
+            .collect();
+        let wifi = entries(root.join("sys/class/net"))?
+            .iter()
+            .any(|net| net.join("wireless").exists() || net.join("phy80211").exists());
+        let bluetooth = !entries(root.join("sys/class/bluetooth"))?.is_empty();
+
+        // Peripherals (mice, headsets) report a battery with a `Device` scope
+        let battery = entries(root.join("sys/class/power_supply"))?.iter().any(|supply| {
+            let read = |name: &str| fs::read_to_string(supply.join(name)).unwrap_or_default();
+            read("type").trim() == "Battery" && read("scope").trim() != "Device"
+        });
+
+        Ok(Self {
+            cpu_vendor,
+            pci,
+            wifi,
+            bluetooth,
+            battery,
+        })
+    }
+
+    /// Display controllers
+    pub fn graphics(&self) -> impl Iterator<Item = &'_ PciDevice> {
+        self.pci.iter().filter(|d| d.base_class() == CLASS_DISPLAY)
+    }
+}
+
+impl Display for Hardware {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if let Some(vendor) = self.cpu_vendor.as_deref() {
+            parts.push(match vendor {
+                "GenuineIntel" => "Intel CPU".into(),
+                "AuthenticAMD" => "AMD CPU".into(),
+                other => format!("{other} CPU"),
+            });
+        }
+        for device in self.graphics() {
+            match device.vendor_name() {
+                Some(name) => parts.push(format!("{name} graphics")),
+                None => parts.push(format!("graphics ({device})")),
+            }
+        }
+        for (present, name) in [
+            (self.wifi, "wifi"),
+            (self.bluetooth, "bluetooth"),
+            (self.battery, "battery"),
+        ] {
+            if present {
+                parts.push(name.into());
+            }
+        }
+        if parts.is_empty() {
+            f.write_str("nothing specific")
+        } else {
+            f.write_str(&parts.join(", "))
+        }
+    }
+}
+
+/// Declarative hardware condition, where every given field must match
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Match {
+    /// CPU vendor, i.e. `GenuineIntel`
+    #[serde(default)]
+    pub cpu_vendor: Option<String>,
+
+    /// Hexadecimal PCI vendor of a device, i.e. `10de`
+    #[serde(default)]
+    pub pci_vendor: Option<String>,
+
+    /// Leading hexadecimal digits of the PCI class of the same device, i.e. `03` for display controllers
+    #[serde(default)]
+    pub pci_class: Option<String>,
+
+    #[serde(default)]
+    pub wifi: Option<bool>,
+
+    #[serde(default)]
+    pub bluetooth: Option<bool>,
+
+    #[serde(default)]
+    pub battery: Option<bool>,
+}
+
+impl Match {
+    /// Whether the hardware satisfies this condition
+    pub fn matches(&self, hardware: &Hardware) -> bool {
+        let cpu = self
+            .cpu_vendor
+            .as_ref()
+            .is_none_or(|v| hardware.cpu_vendor.as_ref() == Some(v));
+        let pci = (self.pci_vendor.is_none() && self.pci_class.is_none())
+            || hardware.pci.iter().any(|device| {
+                let vendor = self
+                    .pci_vendor
+                    .as_ref()
+                    .is_none_or(|v| v.eq_ignore_ascii_case(&format!("{:04x}", device.vendor)));
+                let class = self
+                    .pci_class
+                    .as_ref()
+                    .is_none_or(|c| format!("{:06x}", device.class).starts_with(&c.to_ascii_lowercase()));
+                vendor && class
+            });
+        let flag = |expected: Option<bool>, present: bool| expected.is_none_or(|e| e == present);
+
+        cpu && pci
+            && flag(self.wifi, hardware.wifi)
+            && flag(self.bluetooth, hardware.bluetooth)
+            && flag(self.battery, hardware.battery)
+    }
+}
+
+/// Parse the CPU vendor from the contents of `/proc/cpuinfo`
+fn cpu_vendor(cpuinfo: &str) -> Option<String> {
+    cpuinfo
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim() == "vendor_id")
+        .map(|(_, value)| value.trim().to_owned())
+}
+
+/// Entries of a sysfs directory, none if it doesn't exist
+fn entries(dir: PathBuf) -> Result<Vec<PathBuf>, Error> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    Ok(fs::read_dir(dir)?.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use fs_err as fs;
+
+    use super::{cpu_vendor, Hardware, Match, PciDevice};
+
+    #[test]
+    fn test_probe() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let write = |path: &str, contents: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+        write(
+            "proc/cpuinfo",
+            "processor\t: 0\nvendor_id\t: AuthenticAMD\ncpu family\t: 25\n",
+        );
+        write("sys/bus/pci/devices/0000:01:00.0/vendor", "0x10de\n");
+        write("sys/bus/pci/devices/0000:01:00.0/device", "0x2484\n");
+        write("sys/bus/pci/devices/0000:01:00.0/class", "0x030000\n");
+        write("sys/class/net/wlan0/wireless/.keep", "");
+        write("sys/class/power_supply/hidpp_battery_0/type", "Battery\n");
+        write("sys/class/power_supply/hidpp_battery_0/scope", "Device\n");
+
+        let hardware = Hardware::probe_from(&root).unwrap();
+        assert_eq!(hardware.cpu_vendor.as_deref(), Some("AuthenticAMD"));
+        assert_eq!(
+            hardware.pci,
+            [PciDevice {
+                vendor: 0x10de,
+                device: 0x2484,
+                class: 0x030000
+            }]
+        );
+        assert!(hardware.wifi);
+        assert!(!hardware.bluetooth);
+        assert!(!hardware.battery);
+        assert_eq!(hardware.to_string(), "AMD CPU, NVIDIA graphics, wifi");
+
+        write("sys/class/power_supply/BAT0/type", "Battery\n");
+        assert!(Hardware::probe_from(&root).unwrap().battery);
+
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(Hardware::probe_from(&root).unwrap().to_string(), "nothing specific");
+    }
+
+    #[test]
+    fn test_match() {
+        assert_eq!(
+            cpu_vendor("flags\t: fpu\nvendor_id\t: GenuineIntel\n").as_deref(),
+            Some("GenuineIntel")
+        );
+
+        let hardware = Hardware {
+            cpu_vendor: Some("GenuineIntel".into()),
+            pci: vec![
+                PciDevice {
+                    vendor: 0x8086,
+                    device: 0x9a49,
+                    class: 0x030000,
+                },
+                PciDevice {
+                    vendor: 0x10de,
+                    device: 0x1f9d,
+                    class: 0x030200,
+                },
+            ],
+            battery: true,
+            ..Default::default()
+        };
+        let parse = |json: &str| serde_json::from_str::<Match>(json).unwrap();
+
+        assert!(parse("{}").matches(&hardware));
+        assert!(parse(r#"{"cpu_vendor": "GenuineIntel"}"#).matches(&hardware));
+        assert!(!parse(r#"{"cpu_vendor": "AuthenticAMD"}"#).matches(&hardware));
+        assert!(parse(r#"{"pci_vendor": "10DE", "pci_class": "03"}"#).matches(&hardware));
+        assert!(!parse(r#"{"pci_vendor": "10de", "pci_class": "0300"}"#).matches(&hardware));
+        assert!(parse(r#"{"battery": true, "wifi": false}"#).matches(&hardware));
+        assert!(!parse(r#"{"bluetooth": true}"#).matches(&hardware));
+        assert!(serde_json::from_str::<Match>(r#"{"gpu": "nvidia"}"#).is_err());
+    }
+}
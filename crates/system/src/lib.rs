@@ -5,4 +5,5 @@
 //! Integration with various system APIs to enumerate locales, timezones, etc.
 
 pub mod disk;
+pub mod hardware;
 pub mod locale;
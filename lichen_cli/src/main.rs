@@ -5,28 +5,105 @@
 //! Super basic CLI runner for lichen
 
 use std::{
-    io::Write,
-    path::PathBuf,
-    process::{Command, Output, Stdio},
-    str::FromStr,
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
     time::Duration,
 };
 
-use color_eyre::eyre::ensure;
+use clap::Parser;
+use color_eyre::eyre::{ensure, eyre};
 use console::{set_colors_enabled, style};
 use crossterm::style::Stylize;
-use indicatif::ProgressStyle;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
 use indoc::indoc;
 use installer::{
+    driver::Driver,
+    index::Index,
+    journal::Journal,
+    manifest,
+    offline::Source,
+    plan::{placeholder_uuid, DryRun, Plan},
     selections::{self, Group},
-    steps::Context,
-    systemd, Account, BootPartition, Installer, Locale, SystemPartition,
+    steps::{Context, Event, InstallLog, RecordInstall},
+    strategy::WholeDisk,
+    systemd, Account, BootPartition, Disk, Filesystem, Hardware, Installer, Locale, Manifest, Repository, Subvolume,
+    Swap, SystemPartition,
 };
 use nix::libc::geteuid;
 
+/// Command line arguments parser
+#[derive(Parser)]
+struct Cli {
+    /// Install unattended from the given manifest (TOML, or JSON)
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// Print the installation plan instead of installing. No changes are made.
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Print the plan as JSON
+    #[clap(long, requires = "dry_run")]
+    json: bool,
+
+    /// Ignore the journal of a previous, failed installation instead of resuming it
+    #[clap(long)]
+    fresh: bool,
+
+    /// Load selection groups from this directory instead of the system's
+    #[clap(long, value_name = "DIR")]
+    selections: Option<PathBuf>,
+
+    /// Install from this repository (`name=uri[@priority]`) instead of the official one.
+    /// May be repeated.
+    #[clap(long = "repository", value_name = "NAME=URI")]
+    repositories: Vec<Repository>,
+
+    /// Install without network access, from the repository bundled on the live medium
+    #[clap(long, conflicts_with = "repositories")]
+    offline: bool,
+
+    /// Install without network access, from this stone.index or directory of .stone packages
+    #[clap(long, value_name = "PATH", conflicts_with_all = ["repositories", "offline"])]
+    local_repository: Option<PathBuf>,
+}
+
+impl Cli {
+    /// The local repository to install from, when installing offline
+    fn offline_source(&self) -> color_eyre::Result<Option<Source>> {
+        match (self.local_repository.as_ref(), self.offline) {
+            (Some(path), _) => Ok(Some(Source::from_path(path)?)),
+            (None, true) => Ok(Some(Source::detect()?)),
+            (None, false) => Ok(None),
+        }
+    }
+
+    /// Repositories from the command line replace the configured ones
+    fn repositories(&self, configured: &[Repository], offline: Option<&Source>) -> Vec<Repository> {
+        match offline {
+            Some(source) => vec![source.repository()],
+            None if !self.repositories.is_empty() => self.repositories.clone(),
+            None => configured.to_vec(),
+        }
+    }
+}
+
+/// Where is the installation going?
+#[allow(clippy::large_enum_variant)]
+enum Target<'a> {
+    /// Erase and partition an entire disk
+    WholeDisk(&'a Disk),
+
+    /// Use existing, user-provided partitions: boot, `/` and any extra mounts
+    Partitions(BootPartition, SystemPartition, Vec<SystemPartition>),
+}
+
 #[derive(Debug)]
 struct CliContext {
     root: PathBuf,
+    install_log: InstallLog,
+    progress: ProgressView,
 }
 
 impl<'a> Context<'a> for CliContext {
@@ -35,32 +112,129 @@ impl<'a> Context<'a> for CliContext {
         &self.root
     }
 
-    /// Run a step command
-    /// Right now all output is dumped to stdout/stderr
-    fn run_command(&self, cmd: &mut Command) -> Result<(), installer::steps::Error> {
-        let status = cmd.spawn()?.wait()?;
-        if !status.success() {
-            let program = cmd.get_program().to_string_lossy().into();
-            return Err(installer::steps::Error::CommandFailed { program, status });
-        }
-        Ok(())
+    /// All command output is kept in the install log
+    fn install_log(&self) -> Option<&InstallLog> {
+        Some(&self.install_log)
     }
 
-    /// Run a step command, capture stdout
-    fn run_command_captured(&self, cmd: &mut Command, input: Option<&str>) -> Result<Output, installer::steps::Error> {
-        cmd.stdin(Stdio::piped());
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
-        let mut ps = cmd.spawn()?;
-        let mut stdin = ps.stdin.take().expect("stdin failure");
+    fn emit(&self, event: Event) {
+        self.progress.render(event);
+    }
+}
+
+/// Runs commands on the host while asking questions, before anything is installed
+#[derive(Debug)]
+struct HostContext {
+    root: PathBuf,
+}
 
-        if let Some(input) = input {
-            stdin.write_all(input.as_bytes())?;
+impl<'a> Context<'a> for HostContext {
+    fn root(&'a self) -> &'a PathBuf {
+        &self.root
+    }
+}
+
+/// Renders installation progress with indicatif
+#[derive(Debug)]
+struct ProgressView {
+    multi: MultiProgress,
+    total: ProgressBar,
+
+    /// Bar of the running step, along with its message
+    current: Mutex<Option<(ProgressBar, String)>>,
+}
+
+impl ProgressView {
+    fn new() -> Self {
+        let multi = MultiProgress::new();
+        let total = multi.add(
+            ProgressBar::new(0).with_style(
+                ProgressStyle::with_template("\n|{bar:20.cyan/blue}| {pos}/{len}")
+                    .unwrap()
+                    .progress_chars("■≡=- "),
+            ),
+        );
+        Self {
+            multi,
+            total,
+            current: Mutex::new(None),
         }
-        drop(stdin);
+    }
+
+    fn render(&self, event: Event) {
+        let Ok(mut current) = self.current.lock() else {
+            return;
+        };
+        match event {
+            Event::Started(stage) => {
+                self.total.set_length(stage.total as u64);
+                self.total.set_position(stage.index as u64 + 1);
+                if let Some((progress_bar, _)) = current.take() {
+                    progress_bar.finish();
+                }
+                let title = if stage.cleanup {
+                    stage.title.yellow()
+                } else {
+                    stage.title.blue()
+                };
+                let message = format!("{title} {}", stage.description.bold());
+                let progress_bar = self.multi.insert_before(
+                    &self.total,
+                    ProgressBar::new(1000).with_message(message.clone()).with_style(
+                        ProgressStyle::with_template(" {spinner} {wide_msg} ")
+                            .unwrap()
+                            .tick_chars("--=≡■≡=--"),
+                    ),
+                );
+                progress_bar.enable_steady_tick(Duration::from_millis(150));
+                *current = Some((progress_bar, message));
+            }
+            Event::Skipped(stage) => {
+                self.total.set_length(stage.total as u64);
+                self.total.set_position(stage.index as u64 + 1);
+                self.multi
+                    .println(format!(
+                        "{} {} (already done)",
+                        stage.title.dim(),
+                        stage.description.dim()
+                    ))
+                    .ok();
+            }
+            Event::Finished(_) => {
+                if let Some((progress_bar, _)) = current.take() {
+                    progress_bar.finish();
+                }
+            }
+            Event::Failed { .. } => {
+                if let Some((progress_bar, _)) = current.take() {
+                    progress_bar.abandon();
+                }
+            }
+            Event::Progress { fraction, message } => {
+                if let Some((progress_bar, title)) = current.as_ref() {
+                    progress_bar.set_style(
+                        ProgressStyle::with_template(" {spinner} {msg} |{bar:20.cyan/blue}| {percent}%")
+                            .unwrap()
+                            .tick_chars("--=≡■≡=--")
+                            .progress_chars("■≡=- "),
+                    );
+                    progress_bar.set_position((fraction.clamp(0.0, 1.0) * 1000.0) as u64);
+                    match message {
+                        Some(message) => progress_bar.set_message(format!("{title} {}", message.dim())),
+                        None => progress_bar.set_message(title.clone()),
+                    }
+                }
+            }
+            Event::Log { line } => {
+                if let Some((progress_bar, title)) = current.as_ref() {
+                    progress_bar.set_message(format!("{title} {}", line.trim().dim()));
+                }
+            }
+        }
+    }
 
-        let output = ps.wait_with_output()?;
-        Ok(output)
+    fn clear(&self) -> std::io::Result<()> {
+        self.multi.clear()
     }
 }
 
@@ -93,6 +267,42 @@ fn ask_timezone() -> color_eyre::Result<String> {
     Ok(chrono_tz::TZ_VARIANTS[index].to_string())
 }
 
+/// Use the whole disk, or existing partitions?
+fn ask_whole_disk(disks: &[Disk]) -> color_eyre::Result<bool> {
+    if disks.is_empty() {
+        return Ok(false);
+    }
+    let strategies = [
+        (true, "Use an entire disk", "Erases everything on the disk"),
+        (false, "Use existing partitions", "Requires an ESP and a root partition"),
+    ];
+    let whole_disk = cliclack::select("How would you like to install Serpent OS?")
+        .items(&strategies)
+        .initial_value(true)
+        .interact()?;
+    Ok(whole_disk)
+}
+
+/// Pick the disk to erase
+fn ask_disk(disks: &[Disk]) -> color_eyre::Result<&Disk> {
+    let disks_disp = disks
+        .iter()
+        .enumerate()
+        .map(|(i, d)| {
+            (
+                i,
+                format!("{} {} ({})", d.path.display(), d, HumanBytes(d.size * 512)),
+                "",
+            )
+        })
+        .collect::<Vec<_>>();
+    let index = cliclack::select("Pick a disk to erase and install to")
+        .items(disks_disp.as_slice())
+        .initial_value(0)
+        .interact()?;
+    Ok(&disks[index])
+}
+
 /// Pick an ESP please...
 fn ask_esp(parts: &[BootPartition]) -> color_eyre::Result<&BootPartition> {
     let parts_disp = parts
@@ -111,6 +321,22 @@ fn ask_esp(parts: &[BootPartition]) -> color_eyre::Result<&BootPartition> {
     Ok(&parts[index])
 }
 
+/// Offer to format the ESP (and XBOOTLDR) when they lack a FAT filesystem
+fn ask_format_esp(esp: &BootPartition) -> color_eyre::Result<bool> {
+    if esp.is_formatted() {
+        return Ok(false);
+    }
+    cliclack::log::warning(format!(
+        "{} No FAT file system was detected on {esp}",
+        style("Warning:").bold()
+    ))?;
+    let format = cliclack::confirm("Format the EFI system partition (and XBOOTLDR) as FAT32?")
+        .initial_value(true)
+        .interact()?;
+    ensure!(format, "The EFI system partition must be formatted as FAT32. Exiting.");
+    Ok(format)
+}
+
 /// Where's it going?
 fn ask_rootfs(parts: &[SystemPartition]) -> color_eyre::Result<&SystemPartition> {
     let parts_disp = parts
@@ -129,7 +355,118 @@ fn ask_rootfs(parts: &[SystemPartition]) -> color_eyre::Result<&SystemPartition>
     Ok(&parts[index])
 }
 
-fn ask_filesystem() -> color_eyre::Result<String> {
+/// Optionally use further partitions, i.e. for `/home`
+fn ask_extra_partitions(
+    parts: &[SystemPartition],
+    rootfs: &SystemPartition,
+) -> color_eyre::Result<Vec<SystemPartition>> {
+    let mut chosen: Vec<SystemPartition> = vec![];
+    loop {
+        let available = parts
+            .iter()
+            .filter(|p| {
+                let path = &p.as_ref().path;
+                *path != rootfs.as_ref().path && !chosen.iter().any(|c| c.as_ref().path == *path)
+            })
+            .collect::<Vec<_>>();
+        if available.is_empty() {
+            return Ok(chosen);
+        }
+        let more = cliclack::confirm("Use another partition (i.e. for /home)?")
+            .initial_value(false)
+            .interact()?;
+        if !more {
+            return Ok(chosen);
+        }
+
+        let parts_disp = available
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (i, p.to_string(), ""))
+            .collect::<Vec<_>>();
+        let index = cliclack::select("Pick a partition")
+            .items(parts_disp.as_slice())
+            .initial_value(0)
+            .interact()?;
+        let mountpoint: String = cliclack::input("Where should it be mounted?")
+            .placeholder("/home")
+            .validate(|v: &String| {
+                if !v.starts_with('/') || v == "/" {
+                    return Err("Must be an absolute path other than /");
+                }
+                Ok(())
+            })
+            .interact()?;
+
+        let mut partition = available[index].clone();
+        partition.filesystem = ask_partition_format(&partition, &mountpoint)?;
+        partition.mountpoint = Some(mountpoint);
+        chosen.push(partition);
+    }
+}
+
+/// Keep the existing filesystem on an extra partition, or format it
+fn ask_partition_format(partition: &SystemPartition, mountpoint: &str) -> color_eyre::Result<Option<Filesystem>> {
+    let mut variants = vec![];
+    if let Some(sb) = partition.as_ref().sb {
+        variants.push((None, format!("Keep existing {sb}"), "Existing data is preserved"));
+    }
+    variants.extend(
+        Filesystem::rootfs().map(|f| (Some(*f), format!("Format as {f}"), "Erases everything on the partition")),
+    );
+    let filesystem = cliclack::select(format!("Pick a filesystem for {mountpoint}"))
+        .items(&variants)
+        .initial_value(variants[0].0)
+        .interact()?;
+    Ok(filesystem)
+}
+
+/// How should the new system swap?
+fn ask_swap(parts: &[SystemPartition], used: &[&SystemPartition]) -> color_eyre::Result<Option<Swap>> {
+    let available = parts
+        .iter()
+        .filter(|p| !used.iter().any(|u| u.as_ref().path == p.as_ref().path))
+        .collect::<Vec<_>>();
+
+    let mut variants = vec![
+        ("none", "No swap", ""),
+        ("file", "Swapfile", "Stored on the root filesystem"),
+        ("zram", "zram", "Compressed swap in RAM, for low-disk machines"),
+    ];
+    if !available.is_empty() {
+        variants.push(("partition", "Swap partition", "Erases everything on the partition"));
+    }
+    let kind = cliclack::select("How should the system swap?")
+        .items(&variants)
+        .initial_value("zram")
+        .interact()?;
+
+    let swap = match kind {
+        "file" => {
+            let size: u64 = cliclack::input("Swapfile size (GiB)?").default_input("4").interact()?;
+            Some(Swap::File {
+                size: size * 1024 * 1024 * 1024,
+            })
+        }
+        "zram" => Some(Swap::Zram),
+        "partition" => {
+            let parts_disp = available
+                .iter()
+                .enumerate()
+                .map(|(i, p)| (i, p.to_string(), ""))
+                .collect::<Vec<_>>();
+            let index = cliclack::select("Pick a partition for swap")
+                .items(parts_disp.as_slice())
+                .initial_value(0)
+                .interact()?;
+            Some(Swap::Partition(Box::new(available[index].clone())))
+        }
+        _ => None,
+    };
+    Ok(swap)
+}
+
+fn ask_filesystem() -> color_eyre::Result<Filesystem> {
     let variants = [
         ("xfs", "xfs", "Recommended (fast w/ moss hardlink rollbacks)"),
         (
@@ -142,12 +479,37 @@ fn ask_filesystem() -> color_eyre::Result<String> {
             "ext4",
             "Not Recommended (slow, limited moss hardlink rollback capacity)",
         ),
+        (
+            "btrfs",
+            "btrfs",
+            "Subvolumes for /, /home and /var/log, zstd compression",
+        ),
     ];
-    let index = cliclack::select("Pick a suitable filesystem for the system install root ('/')")
+    let name = cliclack::select("Pick a suitable filesystem for the system install root ('/')")
         .items(&variants)
         .initial_value("xfs")
         .interact()?;
-    Ok(index.into())
+    Ok(name.parse()?)
+}
+
+/// Optionally encrypt `/`, returning the LUKS passphrase
+fn ask_encryption() -> color_eyre::Result<Option<String>> {
+    let encrypt = cliclack::confirm("Encrypt the system install root ('/') with LUKS2?")
+        .initial_value(false)
+        .interact()?;
+    if !encrypt {
+        return Ok(None);
+    }
+    let passphrase = cliclack::password("Pick a disk encryption passphrase").interact()?;
+    let confirmed = cliclack::password("Confirm the passphrase")
+        .validate_interactively(move |v: &String| {
+            if *v != passphrase {
+                return Err("Those passphrases do not match");
+            }
+            Ok(())
+        })
+        .interact()?;
+    Ok(Some(confirmed))
 }
 
 // Grab a password for the root account
@@ -181,18 +543,141 @@ fn create_user() -> color_eyre::Result<Account> {
         .with_shell("/usr/bin/bash"))
 }
 
-fn ask_desktop<'a>(desktops: &'a [&Group]) -> color_eyre::Result<&'a Group> {
-    let displayable = desktops
+/// Known categories in the order they are presented, with their prompt
+const CATEGORIES: &[(&str, &str)] = &[
+    ("desktop", "Pick a desktop environment to use"),
+    ("kernel", "Pick a kernel"),
+    ("development", "Add development tools?"),
+    ("server", "Add server roles?"),
+];
+
+/// Hint for a group: the size of its resolved closure, and its description
+fn describe_group(selections: &selections::Manager, group: &Group, index: Option<&Index>) -> String {
+    let Ok(resolution) = selections.resolve([group.name.as_str()]) else {
+        return group.description.clone();
+    };
+    let count = resolution.packages.len();
+    match index {
+        Some(index) => {
+            let size = index.estimated_size(resolution.packages.keys().map(|n| n.as_str()));
+            format!("{count} packages, ~{} - {}", HumanBytes(size), group.description)
+        }
+        None => format!("{count} packages - {}", group.description),
+    }
+}
+
+/// Ask which groups to install from each category. The mandatory groups are
+/// installed too, but never asked for.
+fn ask_groups(selections: &selections::Manager, index: Option<&Index>) -> color_eyre::Result<Vec<String>> {
+    let categories = selections.categories();
+    let known = CATEGORIES.iter().copied().filter(|(c, _)| categories.contains(c));
+    let others = categories
         .iter()
-        .enumerate()
-        .map(|(i, d)| (i, &d.summary, &d.description))
+        .filter(|c| !CATEGORIES.iter().any(|(known, _)| known == *c))
+        .map(|c| (*c, *c));
+    let categories = known.chain(others).collect::<Vec<_>>();
+
+    loop {
+        let mut chosen = vec![];
+        for (category, prompt) in categories.iter().copied() {
+            let required = selections.is_required(category);
+            let groups = selections.category(category);
+            let items = groups
+                .iter()
+                .map(|g| (g.name.clone(), g.summary.clone(), describe_group(selections, g, index)))
+                .collect::<Vec<_>>();
+            let initial = if required {
+                groups.iter().take(1).map(|g| g.name.clone()).collect()
+            } else {
+                vec![]
+            };
+            chosen.extend(
+                cliclack::multiselect(prompt)
+                    .items(items.as_slice())
+                    .initial_values(initial)
+                    .required(required)
+                    .interact()?,
+            );
+        }
+
+        if resolves(selections, &chosen)? {
+            return Ok(chosen);
+        }
+    }
+}
+
+/// Whether the chosen groups (and the mandatory ones) resolve, reporting any conflict
+fn resolves(selections: &selections::Manager, chosen: &[String]) -> color_eyre::Result<bool> {
+    match selections.resolve(selections.with_mandatory(chosen.iter().map(|g| g.as_str()))) {
+        Ok(_) => Ok(true),
+        Err(selections::Error::Conflict(group, other)) => {
+            cliclack::log::error(format!(
+                "{group} cannot be installed alongside {other}, please choose again"
+            ))?;
+            Ok(false)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Offer the groups supporting the detected hardware, all chosen by default
+fn ask_hardware(
+    selections: &selections::Manager,
+    hardware: &Hardware,
+    index: Option<&Index>,
+) -> color_eyre::Result<Vec<String>> {
+    let detected = selections.detect(hardware);
+    if detected.is_empty() {
+        return Ok(vec![]);
+    }
+    let items = detected
+        .iter()
+        .map(|g| (g.name.clone(), g.summary.clone(), describe_group(selections, g, index)))
         .collect::<Vec<_>>();
-    let index = cliclack::select("Pick a desktop environment to use")
-        .items(displayable.as_slice())
-        .initial_value(1)
-        .interact()?;
+    Ok(
+        cliclack::multiselect(format!("Detected {hardware}. Install hardware support?"))
+            .items(items.as_slice())
+            .initial_values(detected.iter().map(|g| g.name.clone()).collect())
+            .required(false)
+            .interact()?,
+    )
+}
 
-    Ok(desktops[index])
+/// Ask for individual packages to add, or exclude from the `selected` packages when prefixed with `-`
+fn ask_packages(
+    selections: &selections::Manager,
+    selected: &BTreeSet<String>,
+) -> color_eyre::Result<(BTreeSet<String>, BTreeSet<String>)> {
+    loop {
+        let input: String = cliclack::input("Add or remove individual packages? (space separated)")
+            .placeholder("i.e. vim -nano")
+            .default_input("")
+            .required(false)
+            .interact()?;
+        let (exclusions, additions): (Vec<_>, Vec<_>) = input.split_whitespace().partition(|p| p.starts_with('-'));
+        let exclusions = exclusions
+            .into_iter()
+            .map(|p| p.trim_start_matches('-'))
+            .filter(|p| !p.is_empty())
+            .map(String::from)
+            .collect::<BTreeSet<_>>();
+
+        match selections.check_exclusions(selected, exclusions.iter().map(|p| p.as_str())) {
+            Ok(()) => return Ok((additions.into_iter().map(String::from).collect(), exclusions)),
+            Err(e @ (selections::Error::RequiredPackage { .. } | selections::Error::UnmatchedExclusion(_))) => {
+                cliclack::log::error(e.to_string())?
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// The selection groups of the system, or those in `--selections`
+fn load_selections(cli: &Cli) -> color_eyre::Result<selections::Manager> {
+    match cli.selections.as_deref() {
+        Some(dir) => Ok(selections::Manager::load_from(dir, selections::OVERRIDE_DIR)?),
+        None => Ok(selections::Manager::load()?),
+    }
 }
 
 fn main() -> color_eyre::Result<()> {
@@ -200,15 +685,22 @@ fn main() -> color_eyre::Result<()> {
     color_eyre::install().unwrap();
     set_colors_enabled(true);
 
+    let cli = Cli::parse();
+
     let euid = unsafe { geteuid() };
     ensure!(euid == 0, "lichen must be run as root. Re-run with sudo.");
 
+    if let Some(config) = cli.config.as_ref() {
+        return run_unattended(config, &cli);
+    }
+
     let partition_detection_warning = indoc! {"
-        The installer currently does not attempt to detect if there is a file system
-        on detected ESP (and XBOOTLDR) partitions.
+        If you choose to use an entire disk, lichen will erase it and create and
+        format all of the required partitions for you.
 
-        Please ensure that the EFI system partition (ESP) and the Linux extended boot
-        (XBOOTLDR) partition are both formatted as FAT32.
+        Otherwise, the EFI system partition (ESP) and the Linux extended boot
+        (XBOOTLDR) partition must both be formatted as FAT32. Lichen will offer
+        to format them if no FAT file system is detected.
 
         It may be a good idea to check this in gparted (or fdisk) now:
         - The EFI system partition (>=256MiB) should have the flag 'esp' in gparted
@@ -218,7 +710,8 @@ fn main() -> color_eyre::Result<()> {
           - This corresponds to type 142 in fdisk.
 
         NOTE: Users planning to re-install Serpent OS later on, may want to reserve
-              space for a separate /home partition (not handled by this installer).
+              space for a separate /home partition, which can be selected when
+              using existing partitions.
 
         If changes need to be made to partitions, please do so now before continuing.
     "};
@@ -228,25 +721,15 @@ fn main() -> color_eyre::Result<()> {
         partition_detection_warning
     ))?;
 
-    let should_continue = cliclack::confirm("Are you ready to have lichen detect your partitions?").interact()?;
+    let should_continue = cliclack::confirm("Are you ready to have lichen detect your disks?").interact()?;
     ensure!(should_continue, "User chose to abort before detecting partitions.");
 
     cliclack::intro(style("Install Serpent OS").bold())?;
 
-    // Test selection management, force GNOME
-    let selections = selections::Manager::new().with_groups([
-        Group::from_str(include_str!("../../selections/base.json"))?,
-        Group::from_str(include_str!("../../selections/cosmic.json"))?,
-        Group::from_str(include_str!("../../selections/develop.json"))?,
-        Group::from_str(include_str!("../../selections/gnome.json"))?,
-        Group::from_str(include_str!("../../selections/kernel-common.json"))?,
-        Group::from_str(include_str!("../../selections/kernel-desktop.json"))?,
-    ]);
-
-    let desktops = selections
-        .groups()
-        .filter(|g| g.name == "cosmic" || g.name == "gnome")
-        .collect::<Vec<_>>();
+    let selections = load_selections(&cli)?;
+    let offline = cli.offline_source()?;
+
+    let repositories = cli.repositories(&Repository::defaults(), offline.as_ref());
 
     let sp = cliclack::spinner();
     sp.start("Loading");
@@ -255,20 +738,61 @@ fn main() -> color_eyre::Result<()> {
     let inst = Installer::new()?;
     let boots = inst.boot_partitions();
     let parts = inst.system_partitions();
+    let disks = inst.disks();
     let locales = inst.locales_for_ids(systemd::localectl_list_locales()?)?;
 
     sp.clear();
 
-    // TODO: The smart move would be to actually probe the partitions for a valid FS here,
-    //       because we will want to optionally set the partition type and format them
-    //       to the correct fs if this hasn't already been done.
-    let esp = ask_esp(boots)?;
-
-    let mut rootfs = ask_rootfs(parts)?.clone();
-    rootfs.mountpoint = Some("/".into());
+    let target = if ask_whole_disk(disks)? {
+        let disk = ask_disk(disks)?;
+        WholeDisk::new(disk).validate()?;
+        Target::WholeDisk(disk)
+    } else {
+        let mut esp = ask_esp(boots)?.clone();
+        esp.format = ask_format_esp(&esp)?;
+        let mut rootfs = ask_rootfs(parts)?.clone();
+        rootfs.mountpoint = Some("/".into());
+        let extras = ask_extra_partitions(parts, &rootfs)?;
+        Target::Partitions(esp, rootfs, extras)
+    };
     let fs = ask_filesystem()?;
+    let luks_passphrase = ask_encryption()?;
+    let swap = match &target {
+        Target::WholeDisk(_) => ask_swap(&[], &[])?,
+        Target::Partitions(_, rootfs, extras) => {
+            let used = [rootfs].into_iter().chain(extras.iter()).collect::<Vec<_>>();
+            ask_swap(parts, &used)?
+        }
+    };
 
-    let selected_desktop = ask_desktop(&desktops)?;
+    // Sizes need the index of every repository, so remote ones are downloaded
+    let sp = cliclack::spinner();
+    sp.start("Fetching package indexes");
+    let context = HostContext { root: "/".into() };
+    let index = Index::fetch_repositories(&repositories, Index::CACHE_DIR, &context);
+    sp.clear();
+    let index = match index {
+        Ok(index) => Some(index),
+        Err(e) => {
+            cliclack::log::warning(format!("Sizes are unavailable: {e}"))?;
+            None
+        }
+    };
+    let hardware = Hardware::probe().unwrap_or_else(|e| {
+        cliclack::log::warning(format!("Unable to detect hardware: {e}")).ok();
+        Hardware::default()
+    });
+    // Hardware support may conflict with the software, so both are asked again
+    let (groups, hardware_groups) = loop {
+        let mut groups = ask_groups(&selections, index.as_ref())?;
+        let hardware_groups = ask_hardware(&selections, &hardware, index.as_ref())?;
+        groups.extend(hardware_groups.iter().cloned());
+        if resolves(&selections, &groups)? {
+            break (groups, hardware_groups);
+        }
+    };
+    let packages = selections.selections_with(selections.with_mandatory(groups.iter().map(|g| g.as_str())))?;
+    let (additions, exclusions) = ask_packages(&selections, &packages)?;
     let selected_locale = ask_locale(&locales)?;
     let timezone = ask_timezone()?;
     let keyboard_layout_warning = indoc! {"
@@ -288,100 +812,265 @@ fn main() -> color_eyre::Result<()> {
 
     let summary = |title: &str, value: &str| format!("{}: {}", style(title).bold(), value);
 
-    let note = [
+    let mut note = vec![
         summary("Locale", &selected_locale.to_string()),
         summary("Timezone", &timezone),
-        summary("Bootloader", &esp.to_string()),
-        summary("Root (/) partition", &rootfs.to_string()),
-        summary("Root (/) filesystem", &fs),
     ];
+    match &target {
+        Target::WholeDisk(disk) => {
+            note.push(summary(
+                "Disk (will be erased)",
+                &format!("{} {}", disk.path.display(), disk),
+            ));
+            note.push(summary(
+                "New partitions",
+                &format!("\n{}", WholeDisk::new(disk).plan()?),
+            ));
+        }
+        Target::Partitions(esp, rootfs, extras) => {
+            note.push(summary("Bootloader", &esp.to_string()));
+            if esp.format {
+                note.push(summary("Bootloader (will be formatted)", "FAT32"));
+            }
+            note.push(summary("Root (/) partition", &rootfs.to_string()));
+            for extra in extras {
+                let mountpoint = extra.mountpoint.as_deref().unwrap_or_default();
+                let filesystem = match extra.filesystem {
+                    Some(filesystem) => format!("format as {filesystem}"),
+                    None => "keep existing".into(),
+                };
+                note.push(summary(
+                    &format!("{mountpoint} partition"),
+                    &format!("{extra} ({filesystem})"),
+                ));
+            }
+        }
+    }
+    let chosen = groups
+        .iter()
+        .filter(|name| !hardware_groups.contains(name))
+        .filter_map(|name| selections.groups().find(|g| g.name == *name && !g.mandatory))
+        .map(|g| g.summary.as_str())
+        .collect::<Vec<_>>();
+    note.push(summary("Software", &chosen.join(", ")));
+    if !hardware_groups.is_empty() {
+        let support = hardware_groups
+            .iter()
+            .filter_map(|name| selections.groups().find(|g| g.name == *name))
+            .map(|g| g.summary.as_str())
+            .collect::<Vec<_>>();
+        note.push(summary("Hardware support", &support.join(", ")));
+    }
+    if !additions.is_empty() {
+        note.push(summary(
+            "Extra packages",
+            &additions.iter().cloned().collect::<Vec<_>>().join(", "),
+        ));
+    }
+    if !exclusions.is_empty() {
+        note.push(summary(
+            "Excluded packages",
+            &exclusions.iter().cloned().collect::<Vec<_>>().join(", "),
+        ));
+    }
+    note.push(summary("Root (/) filesystem", &fs.to_string()));
+    note.push(summary(
+        "Swap",
+        &swap.as_ref().map(|s| s.describe()).unwrap_or_else(|| "None".into()),
+    ));
+    note.push(summary(
+        "Root (/) encryption",
+        if luks_passphrase.is_some() { "LUKS2" } else { "None" },
+    ));
+
+    if let Some(source) = offline.as_ref() {
+        note.push(summary("Packages (offline)", &source.repository().to_string()));
+    }
 
     cliclack::note("Installation summary", note.join("\n"))?;
 
-    let model = installer::Model {
+    // Plan with the storage as it will be, only touching the disk once installing
+    let target = match target {
+        Target::WholeDisk(disk) => manifest::Target::WholeDisk(disk),
+        Target::Partitions(esp, rootfs, extras) => {
+            manifest::Target::Partitions(esp, [rootfs].into_iter().chain(extras).collect())
+        }
+    };
+    let (boot_partition, partitions) = target.preview()?;
+
+    let plan = installer::Model {
         accounts: [Account::root().with_password(rootpw), user_account].into(),
-        boot_partition: esp.to_owned(),
-        partitions: [rootfs.clone()].into(),
+        boot_partition,
+        partitions,
         locale: Some(selected_locale),
         timezone: Some(timezone),
         rootfs_type: fs,
-        packages: selections.selections_with(["develop", &selected_desktop.name, "kernel-desktop"])?,
+        btrfs_subvolumes: Subvolume::default_layout(),
+        swap,
+        luks_uuid: luks_passphrase.as_ref().map(|_| placeholder_uuid(0)),
+        luks_passphrase,
+        chroot_only: false,
+        groups: groups.clone(),
+        packages,
+        additions,
+        exclusions,
+        repositories,
     };
+    inst.preflight(
+        &plan,
+        &selections,
+        selections.with_mandatory(groups.iter().map(|g| g.as_str())),
+        offline.is_some(),
+    )?;
 
-    let y = cliclack::confirm("Do you want to install?").interact()?;
+    let prompt = if cli.dry_run {
+        "Do you want to see the installation plan?"
+    } else {
+        "Do you want to install?"
+    };
+    let y = cliclack::confirm(prompt).interact()?;
     if !y {
         cliclack::outro_cancel("No changes have been made to your system")?;
         return Ok(());
     }
 
-    cliclack::outro("Now proceeding with installation")?;
+    if cli.dry_run {
+        cliclack::outro("Planning installation, no changes will be made")?;
+    } else {
+        cliclack::outro("Now proceeding with installation")?;
+    }
+
+    if cli.dry_run {
+        show_plan(&inst, &target, &plan, cli.json)
+    } else {
+        install(&inst, target, plan, &cli)
+    }
+}
+
+/// Print everything the installation would do, without touching the system
+fn show_plan(
+    inst: &Installer,
+    target: &manifest::Target<'_>,
+    model: &installer::Model<'_>,
+    json: bool,
+) -> color_eyre::Result<()> {
+    let context = DryRun::new("/tmp/lichen");
+    let (cleanups, steps) = inst.compile_to_steps(model, &context)?;
+    let plan = Plan::new(&steps, &cleanups, &context);
+    let plan = match target {
+        manifest::Target::WholeDisk(disk) => plan.with_partitioning(&WholeDisk::new(disk), &context),
+        manifest::Target::Partitions(..) => plan,
+    };
+    if json {
+        println!("{}", plan.to_json()?);
+    } else {
+        println!("{plan}");
+    }
+    Ok(())
+}
+
+/// Prepare the storage of the plan (or reuse that of a failed run of the same
+/// plan), then compile the model and run all steps, followed by the cleanups
+fn install(
+    inst: &Installer,
+    target: manifest::Target<'_>,
+    plan: installer::Model<'_>,
+    cli: &Cli,
+) -> color_eyre::Result<()> {
+    let mut journal = Journal::open(Journal::DEFAULT_PATH, &plan)?;
+    if cli.fresh {
+        journal.reset();
+    }
 
     // TODO: Use proper temp directory
     let context = CliContext {
         root: "/tmp/lichen".into(),
+        install_log: InstallLog::open(InstallLog::DEFAULT_PATH)?,
+        progress: ProgressView::new(),
+    };
+
+    // Only now is it safe to touch the disk
+    let (boot_partition, partitions) = target.resume(inst, &journal, &context)?;
+    let luks_uuid = match plan.luks_uuid.as_deref() {
+        Some(uuid) if uuid != placeholder_uuid(0) => Some(uuid.to_owned()),
+        Some(_) => match journal.prepared().and_then(|p| p.luks_uuid.clone()) {
+            Some(uuid) => Some(uuid),
+            None => Some(installer::Model::generate_luks_uuid()?),
+        },
+        None => None,
     };
+    let model = installer::Model {
+        boot_partition,
+        partitions,
+        luks_uuid,
+        ..plan
+    };
+    journal.prepare(&model)?;
+
     let (cleanups, steps) = inst.compile_to_steps(&model, &context)?;
-    let multi = indicatif::MultiProgress::new();
-    let total = indicatif::ProgressBar::new(steps.len() as u64 + cleanups.len() as u64).with_style(
-        ProgressStyle::with_template("\n|{bar:20.cyan/blue}| {pos}/{len}")
-            .unwrap()
-            .progress_chars("■≡=- "),
-    );
+    let report = Driver::new(cleanups, steps).with_journal(journal).run(&context);
 
-    let total = multi.add(total);
-    for step in steps {
-        total.inc(1);
-        if step.is_indeterminate() {
-            let progress_bar = multi.insert_before(
-                &total,
-                indicatif::ProgressBar::new(1)
-                    .with_message(format!("{} {}", step.title().blue(), step.describe().bold(),))
-                    .with_style(
-                        ProgressStyle::with_template(" {spinner} {wide_msg} ")
-                            .unwrap()
-                            .tick_chars("--=≡■≡=--"),
-                    ),
-            );
-            progress_bar.enable_steady_tick(Duration::from_millis(150));
-            step.execute(&context)?;
-        } else {
-            multi.println(format!("{} {}", step.title().blue(), step.describe().bold()))?;
-            multi.suspend(|| step.execute(&context))?;
-        }
+    if !report.is_success() {
+        context.progress.clear()?;
+        eprintln!("\n{}\n\n{report}", "Installation failed".red().bold());
+        eprintln!(
+            "The full log is at {}\nRe-run lichen with the same choices to resume from the failed step.\n",
+            context.install_log.path().display()
+        );
+        return Err(eyre!("installation failed"));
     }
 
-    // Execute all the cleanups
-    for cleanup in cleanups {
-        let progress_bar = multi.insert_before(
-            &total,
-            indicatif::ProgressBar::new(1)
-                .with_message(format!("{} {}", cleanup.title().yellow(), cleanup.describe().bold(),))
-                .with_style(
-                    ProgressStyle::with_template(" {spinner} {wide_msg} ")
-                        .unwrap()
-                        .tick_chars("--=≡■≡=--"),
-                ),
-        );
-        progress_bar.enable_steady_tick(Duration::from_millis(150));
-        total.inc(1);
-        cleanup.execute(&context)?;
-    }
-    let home_note = indoc!(
-        "
-        NOTE: If you reserved space for a separate /home partition above, now would
-              be a good time to format it with your filesystem of choice, and to
-              ensure that it is enabled in the /etc/fstab file in the new install.
-
-              Remember to copy/move the new /home/${USER} directory created by the
-              installer in the / partition to the new /home partition.
-        "
-    );
     let installer_success = format!(
         "🎉 🥳 Succesfully installed {}! Reboot now to start using it!",
         style("Serpent OS").bold()
     );
-    multi.clear()?;
-    println!("\n{}\n{}\n", home_note, installer_success);
+    context.progress.clear()?;
+    println!("\n{}\n", installer_success);
+    println!(
+        "{}\n",
+        style(format!(
+            "The answer file for this installation was saved to /{}",
+            RecordInstall::PATH
+        ))
+        .dim()
+    );
 
     Ok(())
 }
+
+/// Install without prompts from a manifest (answer file)
+fn run_unattended(config: &Path, cli: &Cli) -> color_eyre::Result<()> {
+    let manifest = Manifest::from_path(config)?;
+    let selections = load_selections(cli)?;
+    let inst = Installer::new()?;
+    let locales = inst.locales_for_ids(systemd::localectl_list_locales()?)?;
+
+    // Resolve everything up front so nothing is touched on error
+    let target = manifest.validate(&inst, &locales, &selections)?;
+    let offline = cli.offline_source()?;
+    let repositories = cli.repositories(&manifest.repositories, offline.as_ref());
+    manifest::check_repositories(&repositories)?;
+    let (boot_partition, partitions) = target.preview()?;
+    let plan = manifest.to_model(&inst, &locales, &selections, boot_partition, partitions)?;
+    let plan = installer::Model {
+        repositories,
+        // Unless given, the UUID is only generated once installing
+        luks_uuid: manifest
+            .luks_uuid
+            .clone()
+            .or_else(|| plan.luks_uuid.as_ref().map(|_| placeholder_uuid(0))),
+        ..plan
+    };
+    inst.preflight(
+        &plan,
+        &selections,
+        selections.with_mandatory(manifest.groups.iter().map(|g| g.as_str())),
+        offline.is_some(),
+    )?;
+    if cli.dry_run {
+        return show_plan(&inst, &target, &plan, cli.json);
+    }
+    println!("Installing Serpent OS from {}", config.display());
+
+    install(&inst, target, plan, cli)
+}
@@ -152,6 +152,7 @@ pub enum r#Partition_superblock_kind {
     r#ext4,
     r#f2fs,
     r#luks2,
+    r#vfat,
     r#xfs,
     r#unknown,
 }
@@ -246,7 +247,7 @@ pub fn new(inner: Box<dyn VarlinkInterface + Send + Sync>) -> VarlinkInterfacePr
 }
 impl varlink::Interface for VarlinkInterfaceProxy {
     fn get_description(&self) -> &'static str {
-        "# Disk enumeration APIs for Lichen\ninterface com.serpentos.lichen.disks\n\ntype Disk(\n    kind: (ssd, hdd),\n    path: string,\n    model: ?string,\n    vendor: ?string,\n    size: int,\n    block_size: int\n)\n\ntype Partition(\n    path: string,\n    kind: (esp, xbootldr, regular),\n    size: int,\n    uuid: string,\n    superblock_kind: (btrfs, ext4, f2fs, luks2, xfs, unknown)\n)\n\nerror DiskError(\n    message: string\n)\n\n# Enumerate all known disks\nmethod GetDisks() -> (disks:[]Disk)\nmethod GetPartitions(disk: string) -> (partitions:[]Partition)\n"
+        "# Disk enumeration APIs for Lichen\ninterface com.serpentos.lichen.disks\n\ntype Disk(\n    kind: (ssd, hdd),\n    path: string,\n    model: ?string,\n    vendor: ?string,\n    size: int,\n    block_size: int\n)\n\ntype Partition(\n    path: string,\n    kind: (esp, xbootldr, regular),\n    size: int,\n    uuid: string,\n    superblock_kind: (btrfs, ext4, f2fs, luks2, vfat, xfs, unknown)\n)\n\nerror DiskError(\n    message: string\n)\n\n# Enumerate all known disks\nmethod GetDisks() -> (disks:[]Disk)\nmethod GetPartitions(disk: string) -> (partitions:[]Partition)\n"
     }
     fn get_name(&self) -> &'static str {
         "com.serpentos.lichen.disks"
@@ -98,7 +98,9 @@ impl disks_ipc::VarlinkInterface for Service {
                             kind: match p.kind {
                                 system::disk::PartitionKind::ESP => disks_ipc::Partition_kind::esp,
                                 system::disk::PartitionKind::XBOOTLDR => disks_ipc::Partition_kind::xbootldr,
-                                system::disk::PartitionKind::Regular => disks_ipc::Partition_kind::regular,
+                                system::disk::PartitionKind::Root | system::disk::PartitionKind::Regular => {
+                                    disks_ipc::Partition_kind::regular
+                                }
                             },
                             size: p.size as i64,
                             uuid: p.uuid.clone(),
@@ -109,6 +111,7 @@ impl disks_ipc::VarlinkInterface for Service {
                                     system::disk::SuperblockKind::LUKS2 => disks_ipc::Partition_superblock_kind::luks2,
                                     system::disk::SuperblockKind::F2FS => disks_ipc::Partition_superblock_kind::f2fs,
                                     system::disk::SuperblockKind::XFS => disks_ipc::Partition_superblock_kind::xfs,
+                                    system::disk::SuperblockKind::FAT => disks_ipc::Partition_superblock_kind::vfat,
                                 }
                             } else {
                                 disks_ipc::Partition_superblock_kind::unknown